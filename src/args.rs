@@ -10,50 +10,100 @@ use clap::{
     Command,
 };
 
+use data::parse_module_level;
+
+use crate::inputs::{
+    discovery::DEFAULT_INCLUDE, granules::DEFAULT_TIME_PATTERNS, sensors::Platform,
+};
+use crate::outputs::{render::parse_color, writes::COLUMNS};
+
 // #[derive(Debug)]
 // enum Algorithm {
 //     Csv = "csv",
 //     Serde = "serde"
 // }
 
-/// This method examines the input args from sys.args[] and determines
-/// whether the expert flag is set...
-pub fn is_expert(sys_args: &[String]) -> Result<bool, Box<dyn Error>> {
-    let mut is_expert = false;
-
-    let mut sys_args_list = sys_args.to_owned().split_off(1);
-    sys_args_list.sort_unstable();
+/// This function parses a memory size such as "2G", "512M", "64k" or "1000000" into bytes.
+pub fn parse_memory_size(size: &str) -> Result<usize, String> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
 
-    sys_args_list.dedup();
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("Invalid memory size {size:?}, expected e.g. 512M or 2G"))?;
+    let multiplier: f64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" | "KIB" => 1024.0,
+        "M" | "MB" | "MIB" => 1024.0 * 1024.0,
+        "G" | "GB" | "GIB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(format!("Invalid memory size unit {unit:?}, expected one of K, M or G")),
+    };
 
-    let expert_long_idx = sys_args_list.iter().position(|r| r == "--expert");
-
-    let expert_short_idx = sys_args_list.iter().position(|r| r == "-x");
+    Ok((number * multiplier) as usize)
+}
 
-    // if (expert_long_idx != None) || (expert_short_idx != None) {
-    if (expert_long_idx.is_some()) || (expert_short_idx.is_none()) {
-        // log::info!("We have an expert option!");
-        is_expert = true;
+/// This function parses a precision of the form "N", "coords=N" or "stats=N" into the
+/// column group (None for both) and the number of decimal places.
+pub fn parse_precision(precision: &str) -> Result<(Option<String>, usize), String> {
+    let (group, places) = match precision.split_once('=') {
+        Some((group, places)) => (Some(group.trim().to_string()), places),
+        None => (None, precision),
+    };
+    if let Some(group) = &group {
+        if group != "coords" && group != "stats" {
+            return Err(format!("Invalid column group {group:?}, expected coords or stats"));
+        }
     }
+    let places: usize = places
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid precision {places:?}, expected a number of decimal places"))?;
+    Ok((group, places))
+}
 
-    if is_expert {
-        sys_args_list.extend(vec!["--help".to_string()]);
-        sys_args_list.retain(|x| x != "-x" && x != "--expert");
+/// This function parses a rendered value range given as MIN,MAX.
+pub fn parse_range(range: &str) -> Result<(f64, f64), String> {
+    let bounds = range
+        .split_once(',')
+        .and_then(|(low, high)| Some((low.trim().parse::<f64>().ok()?, high.trim().parse::<f64>().ok()?)));
+    match bounds {
+        Some((low, high)) if low < high => Ok((low, high)),
+        _ => Err(format!("Invalid range {range:?}, expected MIN,MAX with MIN below MAX")),
     }
-    sys_args_list.dedup();
+}
 
-    Ok(is_expert)
+/// This function parses the spacing of the graticule lines, in degrees.
+pub fn parse_graticule(spacing: &str) -> Result<f64, String> {
+    match spacing.trim().parse::<f64>() {
+        Ok(degrees) if degrees.is_finite() && degrees > 0.0 => Ok(degrees),
+        _ => Err(format!("Invalid graticule spacing {spacing:?}, expected a positive number of degrees")),
+    }
 }
 
-/// This function collects and handles the command line args using clap.
-pub fn args(sys_args: &[String]) -> Result<ArgMatches, Box<dyn Error>> {
-    // log::info!("Input arguments {sys_args:?}");
+/// This function parses the size of the cells of the projected grids, in km.
+pub fn parse_resolution(resolution: &str) -> Result<f64, String> {
+    match resolution.trim().parse::<f64>() {
+        Ok(km) if km.is_finite() && km > 0.0 => Ok(km),
+        _ => Err(format!("Invalid resolution {resolution:?}, expected a positive number of km")),
+    }
+}
 
-    let expert_args: bool = is_expert(sys_args)?;
+/// This function parses the radius, power and sigma of the remapping, which must be
+/// positive numbers.
+pub fn parse_positive(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => Ok(number),
+        _ => Err(format!("Invalid value {value:?}, expected a positive number")),
+    }
+}
 
-    log::info!("args::args initial sys_args are: {sys_args:?}");
+/// This function builds the clap command. The expert options are hidden from the
+/// help output unless `show_all` is set.
+pub fn command(show_all: bool) -> Command {
+    let expert_args: bool = !show_all;
 
-    let cmd = Command::new("grid_test")
+    Command::new("grid_test")
         .author("Geoff Cureton, geoff.cureton@ssec.wisc.edu")
         .about("Test program for gridding lon/lat data")
         // .help_template("\
@@ -67,20 +117,354 @@ pub fn args(sys_args: &[String]) -> Result<ArgMatches, Box<dyn Error>> {
             .short('i')
             .long("input")
             .value_name("FILE [FILE1, FILE2, ...]")
-            .required(true)
+            .required_unless_present_any(["expert", "file_list", "compare", "render"])
             .action(ArgAction::Set)
             .value_parser(clap::value_parser!(PathBuf))
             .num_args(1..)
-            .help("Input csv file(s)."))
+            .help("Input csv file(s), directories or glob patterns (quote these to stop the shell expanding them). Files compressed with gzip, bzip2, xz or zstd are decompressed, and '-' reads from stdin."))
+        .arg(Arg::new("file_list")
+            .long("file-list")
+            .value_name("FILE")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(PathBuf))
+            .num_args(1)
+            .help("File containing the input paths, one per line. Use '-' to read the list from stdin."))
+        .arg(Arg::new("gridded")
+            .long("gridded")
+            .action(ArgAction::SetTrue)
+            .help("The inputs are gridded outputs of earlier runs (csv or GeoJSON, with the default columns) on the same lon/lat grid. Their cells are merged, and aggregated to any coarser grid size, rather than binning observations."))
+        .arg(Arg::new("start")
+            .long("start")
+            .value_name("TIME")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .help("Only use input granules starting at or after this time, e.g. 2023-09-01T00:00:00Z."))
+        .arg(Arg::new("end")
+            .long("end")
+            .value_name("TIME")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .help("Only use input granules starting before this time, e.g. 2023-09-01T06:00:00Z."))
+        .arg(Arg::new("time_pattern")
+            .long("time-pattern")
+            .value_name("FORMAT")
+            .required(false)
+            .action(ArgAction::Append)
+            .num_args(1)
+            .hide(expert_args)
+            .help(format!("strftime-style pattern used to find the granule time in the input filenames for --start/--end. May be repeated. [default: {}]", DEFAULT_TIME_PATTERNS.join(", "))))
+        .arg(Arg::new("skip_untimed")
+            .long("skip-untimed")
+            .action(ArgAction::SetTrue)
+            .hide(expert_args)
+            .help("Skip the input files whose granule time can't be found for --start/--end, rather than stopping."))
+        .arg(Arg::new("recursive")
+            .short('r')
+            .long("recursive")
+            .action(ArgAction::SetTrue)
+            .help("Descend into the subdirectories of input directories."))
+        .arg(Arg::new("max_depth")
+            .long("max-depth")
+            .value_name("DEPTH")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(usize))
+            .num_args(1)
+            .requires("recursive")
+            .hide(expert_args)
+            .help("Maximum directory depth to descend to with --recursive, where 1 is the contents of the input directory."))
+        .arg(Arg::new("include")
+            .long("include")
+            .value_name("PATTERN")
+            .required(false)
+            .action(ArgAction::Append)
+            .num_args(1)
+            .hide(expert_args)
+            .help(format!("Filename pattern of the files to use from input directories. May be repeated. [default: {}]", DEFAULT_INCLUDE.join(", "))))
+        .arg(Arg::new("exclude")
+            .long("exclude")
+            .value_name("PATTERN")
+            .required(false)
+            .action(ArgAction::Append)
+            .num_args(1)
+            .hide(expert_args)
+            .help("Filename pattern of the input files to skip. May be repeated."))
+        .arg(Arg::new("symlinks")
+            .long("symlinks")
+            .value_name("POLICY")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("follow").help("Follow links to files and directories"),
+                PossibleValue::new("files").help("Follow links to files only"),
+                PossibleValue::new("skip").help("Ignore all links")])
+            .default_value("files")
+            .hide(expert_args)
+            .help("How symbolic links in input directories are handled."))
         .arg(Arg::new("out_file")
             .short('o')
             .long("output")
             .value_name("FILE")
-            .required(true)
+            .required_unless_present("expert")
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(PathBuf))
+            .num_args(1)
+            .help("Output file, written as GeoJSON if it ends in .geojson or .json, as a GeoTIFF if it ends in .tif or .tiff, drawn as a PNG image if it ends in .png, and otherwise as csv. It is compressed if it also ends in .gz, .bz2, .xz or .zst. Use '-' to write csv to stdout."))
+        .arg(Arg::new("sort")
+            .long("sort")
+            .value_name("ORDER")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("lat-major").help("Latitude from north to south, then longitude from west to east"),
+                PossibleValue::new("lon-major").help("Longitude from west to east, then latitude from north to south"),
+                PossibleValue::new("count").help("Largest counts first"),
+                PossibleValue::new("sum").help("Largest sum of heights first"),
+                PossibleValue::new("sum-squared").help("Largest sum of squared heights first"),
+                PossibleValue::new("mean").help("Largest mean height first"),
+                PossibleValue::new("stdev").help("Largest standard deviation first")])
+            .default_value("lon-major")
+            .help("Order of the output cells. Ties are broken in lat-major order. With --full-grid the cells are in the order of the dense grid unless this is given."))
+        .arg(Arg::new("full_grid")
+            .long("full-grid")
+            .action(ArgAction::SetTrue)
+            .help("Write every cell of the grid, including those without any observations."))
+        .arg(Arg::new("fill_value")
+            .long("fill-value")
+            .value_name("VALUE")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .default_value("")
+            .hide(expert_args)
+            .visible_alias("missing-value")
+            .help("Value written for missing statistics (NaN), such as those of empty cells with --full-grid, e.g. NaN or -9999. Empty fields by default."))
+        .arg(Arg::new("precision")
+            .long("precision")
+            .value_name("[GROUP=]N")
+            .required(false)
+            .action(ArgAction::Append)
+            .value_parser(parse_precision)
+            .num_args(1)
+            .help("Decimal places of the floating point values, for both column groups or for just the coordinates (coords=N) or statistics (stats=N). May be repeated. The shortest exact value is written by default."))
+        .arg(Arg::new("scientific")
+            .long("scientific")
+            .action(ArgAction::SetTrue)
+            .hide(expert_args)
+            .help("Write the floating point values in scientific notation."))
+        .arg(Arg::new("columns")
+            .long("columns")
+            .value_name("NAME[=NEW_NAME],...")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_delimiter(',')
+            .num_args(1)
+            .hide(expert_args)
+            .help(format!("Columns to write, in order, optionally renamed. [default: {}]", COLUMNS.join(","))))
+        .arg(Arg::new("geometry")
+            .long("geometry")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("polygons").help("The outline of each cell"),
+                PossibleValue::new("points").help("The center of each cell")])
+            .default_value("polygons")
+            .help("The features written for the cells of a GeoJSON output (.geojson or .json)."))
+        .arg(Arg::new("raster_layout")
+            .long("raster-layout")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("bands").help("One file with a band per statistic"),
+                PossibleValue::new("files").help("One file per statistic, named after the statistic")])
+            .default_value("bands")
+            .help("How the statistics of a GeoTIFF output (.tif or .tiff) are laid out."))
+        .arg(Arg::new("raster_type")
+            .long("raster-type")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(["float32", "float64"])
+            .default_value("float32")
+            .hide(expert_args)
+            .help("The type of the samples of a GeoTIFF output."))
+        .arg(Arg::new("nodata")
+            .long("nodata")
+            .value_name("VALUE")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(clap::value_parser!(f64))
+            .allow_negative_numbers(true)
+            .default_value("nan")
+            .hide(expert_args)
+            .help("The nodata value of a GeoTIFF output, written for the mean and standard deviation of empty cells."))
+        .arg(Arg::new("statistic")
+            .long("statistic")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("mean").help("Mean height"),
+                PossibleValue::new("count").help("Number of observations"),
+                PossibleValue::new("stdev").help("Standard deviation of the heights"),
+                PossibleValue::new("sum").help("Sum of the heights"),
+                PossibleValue::new("sum-squared").help("Sum of the squared heights"),
+                PossibleValue::new("area").help("Cell area")])
+            .default_value("mean")
+            .help("The statistic drawn in a PNG output (.png)."))
+        .arg(Arg::new("colormap")
+            .long("colormap")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("viridis").help("Dark blue through green to yellow"),
+                PossibleValue::new("plasma").help("Dark blue through red to yellow"),
+                PossibleValue::new("gray").help("Black to white"),
+                PossibleValue::new("coolwarm").help("Blue through gray to red, for values either side of a midpoint")])
+            .default_value("viridis")
+            .help("The colormap of a PNG output."))
+        .arg(Arg::new("range")
+            .long("range")
+            .value_name("MIN,MAX")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(parse_range)
+            .allow_hyphen_values(true)
+            .hide(expert_args)
+            .help("The values at the ends of the colormap of a PNG output, beyond which the colors are clamped. The range of the drawn values by default."))
+        .arg(Arg::new("empty_color")
+            .long("empty-color")
+            .value_name("RRGGBB")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(parse_color)
+            .default_value("d0d0d0")
+            .hide(expert_args)
+            .help("The color of the empty cells of a PNG output, as hex digits."))
+        .arg(Arg::new("graticule")
+            .long("graticule")
+            .value_name("DEGREES")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(parse_graticule)
+            .help("Draw lines of longitude and latitude this many degrees apart over a PNG output."))
+        .arg(Arg::new("colorbar")
+            .long("colorbar")
+            .action(ArgAction::SetTrue)
+            .help("Draw a colorbar, labelled with the range of the colormap, below a PNG output."))
+        .arg(Arg::new("render")
+            .long("render")
+            .value_name("GRIDDED")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with_all(["in_file", "file_list", "compare"])
+            .help("Draw the --statistic of a gridded output (csv or GeoJSON, on the lon/lat grid) to the PNG output rather than gridding any inputs, with the colormap, range and other options of PNG outputs."))
+        .arg(Arg::new("regions")
+            .long("regions")
+            .value_name("FILE")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(clap::value_parser!(PathBuf))
+            .conflicts_with("region_mask")
+            .help("Only grid the observations inside the polygons of the regions, read from a GeoJSON file (.geojson or .json), or a file of WKT polygons with one region per line, optionally preceded by its name and a tab."))
+        .arg(Arg::new("region_mask")
+            .long("region-mask")
+            .value_name("FILE")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(clap::value_parser!(PathBuf))
+            .help("Only grid the observations inside the regions of a mask, read from a csv file with the Longitude, Latitude and Region of each cell of a lon/lat grid, such as a land/sea mask."))
+        .arg(Arg::new("region_stats")
+            .long("region-stats")
+            .action(ArgAction::SetTrue)
+            .help("Also write the statistics of each region, to a file named after the output."))
+        .arg(Arg::new("means")
+            .long("means")
+            .value_name("TABLE,...")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_delimiter(',')
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("zonal").help("Mean of each row of latitude"),
+                PossibleValue::new("meridional").help("Mean of each column of longitude"),
+                PossibleValue::new("global").help("Global, hemispheric and latitude band means")])
+            .help("Also write the area-weighted means of the cell mean heights, each table to its own file named after the output."))
+        .arg(Arg::new("weights")
+            .long("weights")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("area").help("The area of each cell"),
+                PossibleValue::new("cos-lat").help("The cosine of the latitude of each cell")])
+            .default_value("area")
+            .hide(expert_args)
+            .help("How the cells are weighted in the means."))
+        .arg(Arg::new("compare")
+            .long("compare")
+            .value_names(["BASE", "NEW"])
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(PathBuf))
+            .num_args(2)
+            .conflicts_with_all(["in_file", "file_list"])
+            .help("Compare two gridded outputs (csv or GeoJSON, with the default columns) on the same grid rather than gridding any inputs, writing the differences of each cell to the output, and the summary metrics to a file named after it."))
+        .arg(Arg::new("tolerance")
+            .long("tolerance")
+            .value_name("HEIGHT")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(clap::value_parser!(f64))
+            .default_value("0")
+            .help("The difference of the mean heights beyond which a cell has changed, with --compare."))
+        .arg(Arg::new("grid_order")
+            .long("grid-order")
+            .value_name("ORDER")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("row-major").help("Rows of latitude from north to south, each from west to east"),
+                PossibleValue::new("column-major").help("Columns of longitude from west to east, each from north to south")])
+            .default_value("row-major")
+            .hide(expert_args)
+            .help("Order of the cells written with --full-grid."))
+        .arg(Arg::new("overwrite")
+            .long("overwrite")
+            .action(ArgAction::SetTrue)
+            .overrides_with("no_clobber")
+            .help("Replace existing output files. This is the default."))
+        .arg(Arg::new("no_clobber")
+            .long("no-clobber")
+            .action(ArgAction::SetTrue)
+            .overrides_with("overwrite")
+            .help("Refuse to replace existing output files."))
+        .arg(Arg::new("report")
+            .long("report")
+            .value_name("FILE")
+            .required(false)
             .action(ArgAction::Set)
             .value_parser(clap::value_parser!(PathBuf))
             .num_args(1)
-            .help("Output csv file."))
+            .help("Write a JSON run report of the inputs, grid, outputs and timings to this file."))
         .arg(Arg::new("satellite")
             .long("satellite")
             .value_name("SATELLITE")
@@ -88,9 +472,7 @@ pub fn args(sys_args: &[String]) -> Result<ArgMatches, Box<dyn Error>> {
             .action(ArgAction::Set)
             .hide(expert_args)
             .num_args(1)
-            .value_parser([
-                PossibleValue::new("him8").help("Himawari-8"),
-                PossibleValue::new("him9").help("Himawari-9")])
+            .value_parser(Platform::ALL.map(|p| PossibleValue::new(p.short_name()).help(p.name())))
             .help("The satellite to run grid_test on. This is only required if automatic detection of the satellite fails."))
         .arg(Arg::new("alg")
             .long("alg")
@@ -109,24 +491,180 @@ pub fn args(sys_args: &[String]) -> Result<ArgMatches, Box<dyn Error>> {
             .default_value("serde")
             .hide(expert_args)
             .help("Algorithm to use for reading input csv file."))
+        .arg(Arg::new("max_records")
+            .long("max-records")
+            .value_name("N")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(usize))
+            .num_args(1)
+            .hide(expert_args)
+            .help("Maximum number of records to read from each input file."))
+        .arg(Arg::new("max_records_total")
+            .long("max-records-total")
+            .value_name("N")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(clap::value_parser!(usize))
+            .num_args(1)
+            .hide(expert_args)
+            .help("Maximum number of records to read from all of the input files."))
+        .arg(Arg::new("memory_limit")
+            .long("memory-limit")
+            .value_name("SIZE")
+            .required(false)
+            .action(ArgAction::Set)
+            .value_parser(parse_memory_size)
+            .num_args(1)
+            .hide(expert_args)
+            .help("Memory to use for holding the input records, e.g. 512M or 2G. Beyond this the records are binned in chunks as they are read."))
         .arg(Arg::new("grid_size")
             .short('g')
             .long("gridsize")
+            .value_name("DEGREES,...")
             .required(false)
             .num_args(1)
             .action(ArgAction::Set)
+            .value_delimiter(',')
             .value_parser(clap::value_parser!(f64))
             .default_value("1.0")
             .hide(expert_args)
             .allow_negative_numbers(false)
-            .help("Longitude/Latitude grid size in degrees."))
+            .help("Longitude/Latitude grid size in degrees, or the grid size of gridded inputs if not given. A list of sizes writes one output per size, named after the size, with the coarser grids aggregated from the finest."))
+        .arg(Arg::new("sensor_grid_size")
+            .long("sensor-gridsize")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("grid_size")
+            .hide(expert_args)
+            .help("Use the default grid size of the detected sensor, rather than 1 degree."))
+        .arg(Arg::new("sensor_ranges")
+            .long("sensor-ranges")
+            .action(ArgAction::SetTrue)
+            .hide(expert_args)
+            .help("Drop the records outside the valid longitude, latitude and height ranges of the detected sensor. All records are kept by default."))
+        .arg(Arg::new("grid")
+            .long("grid")
+            .value_name("GRID")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("lonlat").help("Cells of --gridsize degrees"),
+                PossibleValue::new("ease2").help("Global EASE-Grid 2.0 (equal-area)"),
+                PossibleValue::new("sinusoidal").help("Sinusoidal equal-area grid"),
+                PossibleValue::new("polar-north").help("Polar stereographic grid of the northern hemisphere (true scale at 70N)"),
+                PossibleValue::new("polar-south").help("Polar stereographic grid of the southern hemisphere (true scale at 71S)"),
+                PossibleValue::new("lambert").help("Lambert conformal conic regional grid"),
+                PossibleValue::new("geostationary").help("Native fixed grid of a geostationary imager, in scan angles")])
+            .default_value("lonlat")
+            .help("The grid to bin the observations into. The projected grids have square cells of --resolution km, and write the area of each cell."))
+        .arg(Arg::new("resolution")
+            .long("resolution")
+            .value_name("KM")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(parse_resolution)
+            .default_value("25.0")
+            .help("Size of the cells of the projected grids, in km. For the geostationary grid this is the size below the satellite."))
+        .arg(Arg::new("lambert")
+            .long("lambert")
+            .value_name("LAT_1,LAT_2,LAT_0,LON_0")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_delimiter(',')
+            .value_parser(clap::value_parser!(f64))
+            .allow_negative_numbers(true)
+            .default_value("33,45,40,-97")
+            .hide(expert_args)
+            .help("Standard parallels and origin of the Lambert conformal grid, in degrees."))
+        .arg(Arg::new("sub_lon")
+            .long("sub-lon")
+            .value_name("DEGREES")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(clap::value_parser!(f64))
+            .allow_negative_numbers(true)
+            .hide(expert_args)
+            .help("Sub-satellite longitude of the geostationary grid. Defaults to that of the geostationary --satellite."))
+        .arg(Arg::new("sweep")
+            .long("sweep")
+            .value_name("AXIS")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("x").help("Scanning in azimuth, as the ABI does"),
+                PossibleValue::new("y").help("Scanning in elevation, as the AHI does")])
+            .hide(expert_args)
+            .help("Sweep angle axis of the geostationary grid. Defaults to that of the imager of the --satellite, or y."))
+        .arg(Arg::new("remap")
+            .long("remap")
+            .value_name("METHOD")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("bin").help("Bin each observation into the cell containing it"),
+                PossibleValue::new("idw").help("Inverse-distance weighting of the observations within --radius"),
+                PossibleValue::new("gaussian").help("Gaussian weighting of the observations within --radius"),
+                PossibleValue::new("nearest").help("The observation closest to the cell center, within --radius"),
+                PossibleValue::new("footprint").help("Spread each observation over the cells its pixel footprint overlaps, see --footprint")])
+            .default_value("bin")
+            .help("How the observations are mapped onto the grid."))
+        .arg(Arg::new("radius")
+            .long("radius")
+            .value_name("KM")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(parse_positive)
+            .help("Radius of influence of the observations for --remap, in km. Defaults to the grid size."))
+        .arg(Arg::new("footprint")
+            .long("footprint")
+            .value_name("SOURCE")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("size").help("Along-scan and along-track pixel sizes in km, in the 4th and 5th columns"),
+                PossibleValue::new("zenith").help("Sensor zenith angle in degrees in the 4th column, with the size derived from the scan geometry of the sensor")])
+            .default_value("size")
+            .help("Where the pixel footprints for --remap footprint come from."))
+        .arg(Arg::new("power")
+            .long("power")
+            .value_name("POWER")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(parse_positive)
+            .default_value("2.0")
+            .hide(expert_args)
+            .help("Power of the distance in the inverse-distance weights of --remap idw."))
+        .arg(Arg::new("sigma")
+            .long("sigma")
+            .value_name("KM")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser(parse_positive)
+            .hide(expert_args)
+            .help("Standard deviation of the weights of --remap gaussian, in km. Defaults to half the radius."))
         .arg(Arg::new("verbosity")
             .short('v')
             .long("verbosity")
             .action(ArgAction::Count)
             .value_parser(clap::value_parser!(u8))
             // .default_value("2")
-            .help("Each occurrence increases verbosity 1 level from ERROR: -v=WARNING, -vv=INFO, -vvv=DEBUG"))
+            .help("Each occurrence increases verbosity 1 level from WARNING: -v=INFO, -vv=DEBUG, -vvv=TRACE"))
+        .arg(Arg::new("quiet")
+            .short('q')
+            .long("quiet")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("verbosity")
+            .help("Only log errors."))
         .arg(Arg::new("log_file")
             .short('l')
             .long("logfile")
@@ -137,13 +675,214 @@ pub fn args(sys_args: &[String]) -> Result<ArgMatches, Box<dyn Error>> {
             .num_args(1)
             // .default_value("grid_test.log")
             .hide(expert_args)
-            .help("Output log file."))
+            .help("Output log file. No log file is written unless this is given."))
+        .arg(Arg::new("log_format")
+            .long("log-format")
+            .value_name("FORMAT")
+            .required(false)
+            .action(ArgAction::Set)
+            .num_args(1)
+            .value_parser([
+                PossibleValue::new("text").help("Human readable lines"),
+                PossibleValue::new("json").help("One JSON object per line")])
+            .default_value("text")
+            .hide(expert_args)
+            .help("Format of the log messages."))
+        .arg(Arg::new("log_level")
+            .long("log-level")
+            .value_name("MODULE=LEVEL")
+            .required(false)
+            .action(ArgAction::Append)
+            .value_parser(parse_module_level)
+            .num_args(1)
+            .hide(expert_args)
+            .help("Log level for a single module, e.g. grid_test::inputs=debug. May be repeated."))
         .arg(Arg::new("expert")
             .short('x')
             .long("expert")
+            .visible_alias("help-all")
             .action(ArgAction::SetTrue)
             .value_parser(clap::value_parser!(bool))
-            .help("Display all help options, including the expert ones."));
+            .help("Display all help options, including the expert ones."))
+}
+
+/// This function collects and handles the command line args using clap.
+///
+/// If `--expert` (or `--help-all`) is given, the full help including the expert
+/// options is printed and the program exits.
+pub fn args(sys_args: &[String]) -> Result<ArgMatches, Box<dyn Error>> {
+    log::info!("args::args initial sys_args are: {sys_args:?}");
+
+    let matches = command(false).get_matches_from(sys_args);
+
+    if matches.get_flag("expert") {
+        command(true).print_help()?;
+        std::process::exit(0);
+    }
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::args::{
+        command, parse_graticule, parse_memory_size, parse_positive, parse_precision, parse_range,
+        parse_resolution,
+    };
+
+    const EXPERT_OPTIONS: [&str; 31] = [
+        "--satellite",
+        "--alg",
+        "--gridsize",
+        "--sensor-gridsize",
+        "--sensor-ranges",
+        "--logfile",
+        "--max-depth",
+        "--include",
+        "--exclude",
+        "--symlinks",
+        "--time-pattern",
+        "--skip-untimed",
+        "--max-records",
+        "--max-records-total",
+        "--memory-limit",
+        "--log-format",
+        "--log-level",
+        "--fill-value",
+        "--weights",
+        "--raster-type",
+        "--nodata",
+        "--range",
+        "--empty-color",
+        "--grid-order",
+        "--scientific",
+        "--columns",
+        "--power",
+        "--sigma",
+        "--lambert",
+        "--sub-lon",
+        "--sweep",
+    ];
+
+    #[test]
+    /// The default help should only list the common options.
+    fn help_hides_expert_options() {
+        let help = command(false).render_help().to_string();
+        assert!(help.contains("--input"));
+        assert!(help.contains("--output"));
+        for option in EXPERT_OPTIONS {
+            assert!(!help.contains(option), "{option} should be hidden");
+        }
+    }
+
+    #[test]
+    /// The expert help should list every option.
+    fn help_all_shows_expert_options() {
+        let help = command(true).render_help().to_string();
+        assert!(help.contains("--input"));
+        for option in EXPERT_OPTIONS {
+            assert!(help.contains(option), "{option} should be shown");
+        }
+    }
+
+    #[test]
+    /// The expert flag is accepted without the otherwise required options.
+    fn expert_flag_parses_alone() {
+        for flag in ["-x", "--expert", "--help-all"] {
+            let matches = command(false)
+                .try_get_matches_from(["grid_test", flag])
+                .unwrap();
+            assert!(matches.get_flag("expert"));
+        }
+    }
+
+    #[test]
+    /// A file list may be given instead of the input files.
+    fn file_list_replaces_inputs() {
+        let matches = command(false)
+            .try_get_matches_from(["grid_test", "--file-list", "-", "-o", "b.csv"])
+            .unwrap();
+        assert!(matches.get_many::<std::path::PathBuf>("in_file").is_none());
+        assert!(command(false)
+            .try_get_matches_from(["grid_test", "-o", "b.csv"])
+            .is_err());
+    }
 
-    Ok(cmd.get_matches())
+    #[test]
+    /// Memory sizes are given in bytes, or with a K, M or G suffix.
+    fn memory_sizes() {
+        assert_eq!(parse_memory_size("1000"), Ok(1000));
+        assert_eq!(parse_memory_size("64k"), Ok(64 * 1024));
+        assert_eq!(parse_memory_size("512M"), Ok(512 * 1024 * 1024));
+        assert_eq!(parse_memory_size("1.5GB"), Ok(3 * 512 * 1024 * 1024));
+        assert!(parse_memory_size("lots").is_err());
+        assert!(parse_memory_size("12T").is_err());
+    }
+
+    #[test]
+    /// Precisions are given for both column groups, or for one of them.
+    fn precisions() {
+        assert_eq!(parse_precision("3"), Ok((None, 3)));
+        assert_eq!(parse_precision("stats=2"), Ok((Some("stats".to_string()), 2)));
+        assert!(parse_precision("heights=2").is_err());
+        assert!(parse_precision("coords=many").is_err());
+    }
+
+    #[test]
+    /// Rendered ranges are two increasing values.
+    fn ranges() {
+        assert_eq!(parse_range("-500,12000"), Ok((-500.0, 12000.0)));
+        assert!(parse_range("100,100").is_err());
+        assert!(parse_range("100").is_err());
+    }
+
+    #[test]
+    /// Graticule spacings are a positive number of degrees.
+    fn graticules() {
+        assert_eq!(parse_graticule("30"), Ok(30.0));
+        assert!(parse_graticule("0").is_err());
+        assert!(parse_graticule("-10").is_err());
+        assert!(parse_graticule("NaN").is_err());
+        assert!(parse_graticule("inf").is_err());
+    }
+
+    #[test]
+    /// Resolutions are a positive number of km.
+    fn resolutions() {
+        assert_eq!(parse_resolution("12.5"), Ok(12.5));
+        assert!(parse_resolution("0").is_err());
+        assert!(parse_resolution("-25").is_err());
+        assert!(parse_resolution("NaN").is_err());
+        assert!(parse_resolution("inf").is_err());
+    }
+
+    #[test]
+    /// The radius, power and sigma of the remapping are positive numbers.
+    fn positives() {
+        assert_eq!(parse_positive("0.5"), Ok(0.5));
+        assert!(parse_positive("0").is_err());
+        assert!(parse_positive("-2").is_err());
+        assert!(parse_positive("NaN").is_err());
+        assert!(parse_positive("inf").is_err());
+    }
+
+    #[test]
+    /// The expert options are still accepted when hidden from the help.
+    fn hidden_options_still_parse() {
+        let matches = command(false)
+            .try_get_matches_from(["grid_test", "-i", "a.csv", "-o", "b.csv", "-g", "0.5"])
+            .unwrap();
+        assert_eq!(matches.get_one::<f64>("grid_size"), Some(&0.5));
+        assert!(!matches.get_flag("expert"));
+    }
+
+    #[test]
+    /// The grid size may be a list of sizes.
+    fn grid_size_list() {
+        let matches = command(false)
+            .try_get_matches_from(["grid_test", "-i", "a.csv", "-o", "b.csv", "-g", "0.1,0.25,1"])
+            .unwrap();
+        let grid_sizes: Vec<f64> = matches.get_many("grid_size").unwrap().copied().collect();
+        assert_eq!(grid_sizes, [0.1, 0.25, 1.0]);
+    }
 }
@@ -1,18 +1,57 @@
 use std::collections::HashMap;
 use std::error::Error;
 
-pub const GRID_SIZE: f64 = 1.0;
-pub const MAX_RECORDS: usize = 10_000_000;
+pub mod compare; // src/compute/compare.rs
+pub mod grids; // src/compute/grids.rs
+pub mod means; // src/compute/means.rs
+pub mod remap; // src/compute/remap.rs
+pub mod summaries; // src/compute/summaries.rs
 
-use crate::inputs::ReadRecord;
+use crate::inputs::{sensors::GridExtent, ReadRecord};
 use crate::outputs::WriteRecord;
+use grids::Grid;
 
-/// The HeightData struct holds binned observations of a gridcell
-/// Also stored is the number of observations for that gridcell
+/// The order of the cells of a full grid, matching the layout of a dense grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GridOrder {
+    /// Rows of latitude from north to south, each from west to east.
+    RowMajor,
+    /// Columns of longitude from west to east, each from north to south.
+    ColumnMajor,
+}
+
+impl GridOrder {
+    pub fn from_name(name: &str) -> Option<GridOrder> {
+        match name {
+            "row-major" => Some(GridOrder::RowMajor),
+            "column-major" => Some(GridOrder::ColumnMajor),
+            _ => None,
+        }
+    }
+}
+
+/// The HeightData struct holds the number of observations binned into a gridcell,
+/// and the sums of their heights and squared heights. Only these sums are kept,
+/// so the memory used depends on the number of cells rather than of observations.
 #[derive(Debug, Clone, PartialEq)]
 pub struct HeightData {
     counts: i64,
-    heights: Vec<i64>,
+    sum_heights: i64,
+    sum_squared_heights: i64,
+}
+
+impl HeightData {
+    fn add(&mut self, height: i64) {
+        self.counts += 1;
+        self.sum_heights += height;
+        self.sum_squared_heights += height * height;
+    }
+
+    fn merge(&mut self, other: &HeightData) {
+        self.counts += other.counts;
+        self.sum_heights += other.sum_heights;
+        self.sum_squared_heights += other.sum_squared_heights;
+    }
 }
 
 /// This function accepts as input an iterator over the lines of a string, and bins the data
@@ -21,14 +60,38 @@ pub fn generate_histograms(
     csv_records: &[ReadRecord],
     grid_size: &f64,
 ) -> Result<HashMap<String, HeightData>, Box<dyn Error>> {
-    log::info!("Binning the csv records into a histogram...");
-
-    // let mut idx = 0;
     let mut grid_dict: HashMap<String, HeightData> = HashMap::new();
+    bin_records(&mut grid_dict, csv_records, grid_size)?;
+
+    Ok(grid_dict)
+}
+
+/// This function bins the records into an existing HashMap of grid cells, so that
+/// records read in chunks can be accumulated into the same grid.
+pub fn bin_records(
+    grid_dict: &mut HashMap<String, HeightData>,
+    csv_records: &[ReadRecord],
+    grid_size: &f64,
+) -> Result<(), Box<dyn Error>> {
+    let grid = Grid::LonLat {
+        grid_size: *grid_size,
+    };
+    bin_records_on_grid(grid_dict, csv_records, &grid)
+}
+
+/// This function bins the records into the cells of the grid, which may be a
+/// projected grid. Records outside the domain of the projection are skipped.
+pub fn bin_records_on_grid(
+    grid_dict: &mut HashMap<String, HeightData>,
+    csv_records: &[ReadRecord],
+    grid: &Grid,
+) -> Result<(), Box<dyn Error>> {
+    log::info!("Binning the csv records into a histogram...");
 
     let mut num_records: i64 = 0;
+    let mut num_outside: i64 = 0;
 
-    for (_idx, record) in csv_records.iter().enumerate() {
+    for record in csv_records {
         num_records += 1;
 
         let longitude = record.longitude;
@@ -36,31 +99,169 @@ pub fn generate_histograms(
         let height = record.height;
 
         // Compute the grid cell coordinates for this observation
-        let lon_center = grid_size * (longitude / grid_size).floor() + grid_size / 2.0;
-        let lat_center = grid_size * (latitude / grid_size).floor() + grid_size / 2.0;
+        let Some((column, row)) = grid.cell_index(longitude, latitude) else {
+            num_outside += 1;
+            continue;
+        };
+        let (lon_center, lat_center) = grid.cell_center(column, row);
 
         // Here we are making a string key from the lat and lon center values.
         // We can also make a key from a struct containing these values, as long as they
         // derive the Eq and Hash PartialEq traits.
 
-        let key = format!("({lon_center:6.1},{lat_center:6.1})");
+        let key = cell_key(lon_center, lat_center);
 
         // Add this observation to the corresponding grid cell
         grid_dict
             .entry(key)
-            .and_modify(|hgt| {
-                hgt.counts += 1;
-                hgt.heights.push(height);
-            })
             .or_insert(HeightData {
-                counts: 1,
-                heights: vec![height],
-            });
+                counts: 0,
+                sum_heights: 0,
+                sum_squared_heights: 0,
+            })
+            .add(height);
+    }
+    if num_outside > 0 {
+        log::warn!("Skipped {num_outside} records outside the domain of the {grid} grid");
     }
     log::info!("Finished binning the csv records.");
     log::info!("There are {:?} csv records\n", num_records);
 
-    Ok(grid_dict)
+    Ok(())
+}
+
+/// The key of the grid cell with the given center.
+fn cell_key(lon_center: f64, lat_center: f64) -> String {
+    format!("({lon_center:11.6},{lat_center:10.6})")
+}
+
+/// The center of the grid cell with the given key.
+fn parse_cell_key(key: &str) -> Result<(f64, f64), Box<dyn Error>> {
+    let v: Vec<&str> = key.split(',').collect();
+    let longitude = v[0].trim_matches('(').trim_matches(' ').parse::<f64>()?;
+    let latitude = v[1].trim_matches(' ').trim_matches(')').parse::<f64>()?;
+    Ok((longitude, latitude))
+}
+
+/// Coordinates and grid sizes are compared in whole micro-degrees, well below the
+/// precision of the keys.
+pub(crate) const MICRO_DEGREES: f64 = 1.0e6;
+
+/// The nearest whole number of micro-degrees to a coordinate or grid size.
+pub(crate) fn micro_degrees(value: f64) -> i64 {
+    (value * MICRO_DEGREES).round() as i64
+}
+
+/// The greatest common divisor of two whole numbers of micro-degrees.
+pub(crate) fn gcd(a: i64, b: i64) -> i64 {
+    match b {
+        0 => a.abs(),
+        _ => gcd(b, a % b),
+    }
+}
+
+/// This function finds the largest grid size which divides every one of the grid
+/// sizes, so that each of their cells is made up of whole cells of that size. This
+/// is the finest grid size when each grid size is a multiple of it.
+pub fn common_grid_size(grid_sizes: &[f64]) -> Result<f64, Box<dyn Error>> {
+    let mut common: i64 = 0;
+    for grid_size in grid_sizes {
+        let micro_degrees = micro_degrees(*grid_size);
+        if micro_degrees < 1 || (grid_size * MICRO_DEGREES - micro_degrees as f64).abs() > 1.0e-6 {
+            Err(format!(
+                "The grid size {grid_size} is not a whole number of micro-degrees."
+            ))?
+        }
+        common = gcd(micro_degrees, common);
+    }
+    Ok(common as f64 / MICRO_DEGREES)
+}
+
+/// This function aggregates the cells of a fine grid into the cells of a coarser
+/// grid, whose size is a multiple of the fine grid size. Each fine cell lies wholly
+/// inside one coarse cell, and its sums are added to that cell, so the
+/// statistics are exactly those of binning the records at the coarse size.
+pub fn aggregate_cells(
+    grid_dict: &HashMap<String, HeightData>,
+    grid_size: f64,
+) -> Result<HashMap<String, HeightData>, Box<dyn Error>> {
+    log::info!(
+        "Aggregating {} grid cells into {grid_size} degree cells...",
+        grid_dict.len()
+    );
+
+    let grid = Grid::LonLat { grid_size };
+    let mut coarse_dict: HashMap<String, HeightData> = HashMap::new();
+    for (key, cell) in grid_dict {
+        let (lon_center, lat_center) = parse_cell_key(key)?;
+        let Some((column, row)) = grid.cell_index(lon_center, lat_center) else {
+            continue;
+        };
+        let (lon_center, lat_center) = grid.cell_center(column, row);
+
+        coarse_dict
+            .entry(cell_key(lon_center, lat_center))
+            .and_modify(|hgt| hgt.merge(cell))
+            .or_insert_with(|| cell.clone());
+    }
+    log::info!("There are {} aggregated grid cells", coarse_dict.len());
+
+    Ok(coarse_dict)
+}
+
+/// This function merges the populated cells of gridded outputs, on the lon/lat grid
+/// of the input grid size, into the cells of a grid whose size is a multiple of it.
+/// The counts and sums of the cells falling in each cell are added, and the mean and
+/// standard deviation found from them, so the statistics are exactly those of
+/// binning the original records.
+pub fn merge_cells(
+    csv_records: &[WriteRecord],
+    input_grid_size: f64,
+    grid_size: f64,
+) -> Result<Vec<WriteRecord>, Box<dyn Error>> {
+    if common_grid_size(&[input_grid_size, grid_size])? != input_grid_size {
+        Err(format!(
+            "The grid size {grid_size} is not a multiple of the {input_grid_size} degree grid of the gridded inputs."
+        ))?
+    }
+    log::info!(
+        "Merging {} gridded cells into {grid_size} degree cells...",
+        csv_records.len()
+    );
+
+    let grid = Grid::LonLat { grid_size };
+    let mut merged: HashMap<String, WriteRecord> = HashMap::new();
+    for record in csv_records.iter().filter(|record| record.counts > 0) {
+        let Some((column, row)) = grid.cell_index(record.longitude, record.latitude) else {
+            continue;
+        };
+        let (longitude, latitude) = grid.cell_center(column, row);
+
+        merged
+            .entry(cell_key(longitude, latitude))
+            .and_modify(|cell| {
+                cell.counts += record.counts;
+                cell.sum_heights += record.sum_heights;
+                cell.sum_squared_heights += record.sum_squared_heights;
+            })
+            .or_insert_with(|| WriteRecord {
+                longitude,
+                latitude,
+                cell_area: f64::NAN,
+                ..record.clone()
+            });
+    }
+
+    let mut csv_records: Vec<WriteRecord> = merged.into_values().collect();
+    for cell in &mut csv_records {
+        let mom_1: f64 = cell.sum_heights as f64 / cell.counts as f64;
+        let mom_2: f64 = cell.sum_squared_heights as f64 / cell.counts as f64;
+        cell.mean_height = mom_1;
+        cell.stdev_height = (mom_2 - mom_1 * mom_1).max(0.0).sqrt();
+    }
+    log::info!("There are {} merged grid cells", csv_records.len());
+
+    Ok(csv_records)
 }
 
 /// This function reads the contents of a HashMap, computes some statistics for each key,
@@ -70,34 +271,21 @@ pub fn calc_stats(
 ) -> Result<Vec<WriteRecord>, Box<dyn Error>> {
     log::info!("Calculating the stats for each grid cell...");
 
-    let unsorted_keys: Vec<String> = grid_dict.clone().into_keys().collect();
-    let mut sorted_keys = unsorted_keys.clone();
-    sorted_keys.sort_unstable();
+    let num_keys = grid_dict.len();
 
-    log::info!("There are {} unsorted keys...", unsorted_keys.len());
-    log::info!("There are {} sorted keys...", sorted_keys.len());
+    // The cells are in no particular order, see sort_records
+    let mut csv_records: Vec<WriteRecord> = Vec::with_capacity(num_keys);
 
-    let num_keys = sorted_keys.len();
+    for (key, cell) in grid_dict {
+        let (longitude, latitude) = parse_cell_key(key)?;
 
-    // let mut csv_records: Vec<WriteRecord> = Vec::new();
-    let mut csv_records: Vec<WriteRecord> = Vec::with_capacity(sorted_keys.len());
-
-    for key in sorted_keys {
-        let v: Vec<&str> = key.split(',').collect();
-        let longitude = v[0].trim_matches('(').trim_matches(' ').parse::<f64>()?;
-        let latitude = v[1].trim_matches(' ').trim_matches(')').parse::<f64>()?;
-
-        let heights = &grid_dict.get(&key).unwrap().heights;
-        let counts = &grid_dict.get(&key).unwrap().counts;
-
-        // let sum_heights: i64 = heights.iter().sum::<i64>();
-        let sum_heights: i64 = heights.iter().sum();
-        // let sum_squared_heights: i64 = heights.iter().map(|x| (*x) * (*x)).sum();
-        let sum_squared_heights: i64 = heights.iter().map(|x| x * x).sum();
+        let counts = &cell.counts;
+        let sum_heights: i64 = cell.sum_heights;
+        let sum_squared_heights: i64 = cell.sum_squared_heights;
 
         let mom_1: f64 = sum_heights as f64 / (*counts as f64);
         let mom_2: f64 = sum_squared_heights as f64 / (*counts as f64);
-        let cum_2: f64 = mom_2 - mom_1 * mom_1;
+        let cum_2: f64 = (mom_2 - mom_1 * mom_1).max(0.0);
 
         let mean_height: f64 = mom_1;
         let stdev_height: f64 = cum_2.sqrt();
@@ -110,6 +298,7 @@ pub fn calc_stats(
             sum_squared_heights,
             mean_height,
             stdev_height,
+            cell_area: f64::NAN,
         });
     }
 
@@ -119,19 +308,410 @@ pub fn calc_stats(
     Ok(csv_records)
 }
 
+/// The order of the output cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Latitude from north to south, then longitude from west to east.
+    LatMajor,
+    /// Longitude from west to east, then latitude from north to south.
+    LonMajor,
+    /// A statistic from largest to smallest, then lat-major.
+    Counts,
+    SumHeights,
+    SumSquaredHeights,
+    MeanHeight,
+    StdevHeight,
+}
+
+impl SortOrder {
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        match name {
+            "lat-major" => Some(SortOrder::LatMajor),
+            "lon-major" => Some(SortOrder::LonMajor),
+            "count" => Some(SortOrder::Counts),
+            "sum" => Some(SortOrder::SumHeights),
+            "sum-squared" => Some(SortOrder::SumSquaredHeights),
+            "mean" => Some(SortOrder::MeanHeight),
+            "stdev" => Some(SortOrder::StdevHeight),
+            _ => None,
+        }
+    }
+
+    /// The statistic sorted on, as a float. NaN (from empty cells) sorts last.
+    fn statistic(&self, record: &WriteRecord) -> Option<f64> {
+        let value = match self {
+            SortOrder::LatMajor | SortOrder::LonMajor => return None,
+            SortOrder::Counts => record.counts as f64,
+            SortOrder::SumHeights => record.sum_heights as f64,
+            SortOrder::SumSquaredHeights => record.sum_squared_heights as f64,
+            SortOrder::MeanHeight => record.mean_height,
+            SortOrder::StdevHeight => record.stdev_height,
+        };
+        Some(if value.is_nan() {
+            f64::NEG_INFINITY
+        } else {
+            value
+        })
+    }
+}
+
+/// This function sorts the cells into the given order. The sort is stable and
+/// compares the numeric coordinates, so the order doesn't depend on the grid size
+/// or on how the cells are formatted.
+pub fn sort_records(csv_records: &mut [WriteRecord], order: SortOrder) {
+    let lat_major = |a: &WriteRecord, b: &WriteRecord| {
+        b.latitude
+            .total_cmp(&a.latitude)
+            .then(a.longitude.total_cmp(&b.longitude))
+    };
+    match order {
+        SortOrder::LatMajor => csv_records.sort_by(lat_major),
+        SortOrder::LonMajor => csv_records.sort_by(|a, b| {
+            a.longitude
+                .total_cmp(&b.longitude)
+                .then(b.latitude.total_cmp(&a.latitude))
+        }),
+        _ => csv_records.sort_by(|a, b| {
+            let (a_value, b_value) = (order.statistic(a).unwrap(), order.statistic(b).unwrap());
+            b_value.total_cmp(&a_value).then(lat_major(a, b))
+        }),
+    }
+}
+
+/// This function sets the area of each cell of the grid, in km².
+pub fn set_cell_areas(csv_records: &mut [WriteRecord], grid: &Grid) {
+    for record in csv_records {
+        record.cell_area = grid.cell_area_at(record.longitude, record.latitude);
+    }
+}
+
+/// The index of the grid cell holding the coordinate.
+fn cell_index(coordinate: f64, grid_size: f64) -> i64 {
+    (coordinate / grid_size).floor() as i64
+}
+
+/// The number of (columns, rows) of the full grid over the extent, as filled by `fill_grid`.
+pub fn grid_dimensions(grid_size: f64, extent: &GridExtent) -> (usize, usize) {
+    let columns = (extent.east / grid_size).ceil() as i64 - cell_index(extent.west, grid_size);
+    let rows = (extent.north / grid_size).ceil() as i64 - cell_index(extent.south, grid_size);
+    (columns.max(0) as usize, rows.max(0) as usize)
+}
+
+/// This function expands the populated cells to every cell of the grid over the
+/// extent, in the given order. The empty cells have counts of zero and NaN mean
+/// and standard deviation. Populated cells outside the extent are dropped.
+pub fn fill_grid(
+    csv_records: Vec<WriteRecord>,
+    grid_size: f64,
+    extent: &GridExtent,
+    order: GridOrder,
+) -> Vec<WriteRecord> {
+    log::info!("Filling the empty cells of the grid over {extent}...");
+
+    let mut populated: HashMap<(i64, i64), WriteRecord> = csv_records
+        .into_iter()
+        .map(|record| {
+            let index = (
+                cell_index(record.longitude, grid_size),
+                cell_index(record.latitude, grid_size),
+            );
+            (index, record)
+        })
+        .collect();
+
+    // Cells east of the antimeridian are wrapped back into [-180, 180)
+    let columns: Vec<i64> = (cell_index(extent.west, grid_size)
+        ..(extent.east / grid_size).ceil() as i64)
+        .map(|i| {
+            let lon_center = grid_size * i as f64 + grid_size / 2.0;
+            match lon_center > 180.0 {
+                true => cell_index(lon_center - 360.0, grid_size),
+                false => i,
+            }
+        })
+        .collect();
+    let rows: Vec<i64> = (cell_index(extent.south, grid_size)
+        ..(extent.north / grid_size).ceil() as i64)
+        .rev()
+        .collect();
+
+    let cells: Vec<(i64, i64)> = match order {
+        GridOrder::RowMajor => rows
+            .iter()
+            .flat_map(|row| columns.iter().map(move |column| (*column, *row)))
+            .collect(),
+        GridOrder::ColumnMajor => columns
+            .iter()
+            .flat_map(|column| rows.iter().map(move |row| (*column, *row)))
+            .collect(),
+    };
+
+    let csv_records: Vec<WriteRecord> = cells
+        .into_iter()
+        .map(|(column, row)| {
+            populated
+                .remove(&(column, row))
+                .unwrap_or_else(|| WriteRecord {
+                    longitude: grid_size * column as f64 + grid_size / 2.0,
+                    latitude: grid_size * row as f64 + grid_size / 2.0,
+                    counts: 0,
+                    sum_heights: 0,
+                    sum_squared_heights: 0,
+                    mean_height: f64::NAN,
+                    stdev_height: f64::NAN,
+                    cell_area: f64::NAN,
+                })
+        })
+        .collect();
+
+    if !populated.is_empty() {
+        log::warn!(
+            "Dropped {} populated grid cells outside the grid extent {extent}",
+            populated.len()
+        );
+    }
+    log::info!("There are {} cells in the full grid", csv_records.len());
+
+    csv_records
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::HeightData;
+    use std::collections::HashMap;
+
+    use crate::compute::{
+        aggregate_cells, bin_records, calc_stats, common_grid_size, fill_grid, generate_histograms,
+        merge_cells, sort_records, GridOrder, SortOrder,
+    };
+    use crate::inputs::sensors::GridExtent;
+    use crate::{HeightData, ReadRecord, WriteRecord};
+
+    /// Checks the cells have the same centers, sums and statistics, in the same order.
+    fn assert_same_cells(cells: &[WriteRecord], expected: &[WriteRecord]) {
+        let values = |cells: &[WriteRecord]| -> Vec<(f64, f64, i64, i64, i64, f64, f64)> {
+            cells
+                .iter()
+                .map(|r| {
+                    (
+                        r.longitude,
+                        r.latitude,
+                        r.counts,
+                        r.sum_heights,
+                        r.sum_squared_heights,
+                        r.mean_height,
+                        r.stdev_height,
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(values(cells), values(expected));
+    }
 
     #[test]
     /// This test checks that the struct attributes are the values
     /// they were defined as.
     fn heights_struct_test() {
         let cell_1 = HeightData {
-            counts: 25,
-            heights: vec![1266, 12656, 5256, 735],
+            counts: 4,
+            sum_heights: 19913,
+            sum_squared_heights: 189_942_853,
         };
         let cell_2 = cell_1.clone();
         assert_eq!(cell_1, cell_2);
     }
+
+    #[test]
+    /// Binning the records in chunks gives the same grid as binning them together.
+    fn chunked_binning_matches() {
+        let records: Vec<ReadRecord> = (0..20)
+            .map(|i| ReadRecord {
+                longitude: 100.0 + i as f64 * 0.3,
+                latitude: -30.0 + i as f64 * 0.2,
+                height: 1000 + i,
+                footprint: None,
+            })
+            .collect();
+        let grid_dict = generate_histograms(&records, &1.0).unwrap();
+
+        let mut chunked_dict: HashMap<String, HeightData> = HashMap::new();
+        for chunk in records.chunks(3) {
+            bin_records(&mut chunked_dict, chunk, &1.0).unwrap();
+        }
+        assert_eq!(grid_dict, chunked_dict);
+    }
+
+    #[test]
+    /// Aggregating a fine grid gives the same statistics as binning at the coarse size.
+    fn aggregated_grid_matches() {
+        assert_eq!(common_grid_size(&[0.1, 0.25, 0.5, 1.0]).unwrap(), 0.05);
+        assert_eq!(common_grid_size(&[0.25, 1.0]).unwrap(), 0.25);
+        assert!(common_grid_size(&[1.0e-9]).is_err());
+
+        let records: Vec<ReadRecord> = (0..200)
+            .map(|i| ReadRecord {
+                longitude: -180.0 + i as f64 * 1.737,
+                latitude: -89.0 + i as f64 * 0.883,
+                height: 1000 + 7 * i,
+                footprint: None,
+            })
+            .collect();
+        let fine_dict = generate_histograms(&records, &0.05).unwrap();
+
+        let stats = |grid_dict: &HashMap<String, HeightData>| {
+            let mut csv_records = calc_stats(grid_dict).unwrap();
+            sort_records(&mut csv_records, SortOrder::LatMajor);
+            csv_records
+        };
+        for grid_size in [0.25, 0.5, 1.0] {
+            let coarse_dict = aggregate_cells(&fine_dict, grid_size).unwrap();
+            let binned_dict = generate_histograms(&records, &grid_size).unwrap();
+            let (coarse, binned) = (stats(&coarse_dict), stats(&binned_dict));
+            assert_same_cells(&coarse, &binned);
+        }
+    }
+
+    #[test]
+    /// Merging the cells of gridded outputs gives the statistics of binning all their records.
+    fn merged_cells_match() {
+        let records: Vec<ReadRecord> = (0..200)
+            .map(|i| ReadRecord {
+                longitude: 10.0 + (i % 17) as f64 * 0.31,
+                latitude: -5.0 + (i % 13) as f64 * 0.27,
+                height: 1000 + 7 * i,
+                footprint: None,
+            })
+            .collect();
+        let mut gridded: Vec<WriteRecord> = Vec::new();
+        for half in records.chunks(100) {
+            gridded.extend(calc_stats(&generate_histograms(half, &0.25).unwrap()).unwrap());
+        }
+
+        for grid_size in [0.25, 1.0] {
+            let mut merged = merge_cells(&gridded, 0.25, grid_size).unwrap();
+            let mut binned =
+                calc_stats(&generate_histograms(&records, &grid_size).unwrap()).unwrap();
+            sort_records(&mut merged, SortOrder::LatMajor);
+            sort_records(&mut binned, SortOrder::LatMajor);
+            assert_same_cells(&merged, &binned);
+        }
+        assert!(merge_cells(&gridded, 0.25, 0.3).is_err());
+    }
+
+    #[test]
+    /// Every cell of the extent is written in order, with the populated cells kept.
+    fn full_grid_order() {
+        let records = vec![ReadRecord {
+            longitude: 1.2,
+            latitude: -0.5,
+            height: 1000,
+            footprint: None,
+        }];
+        let grid_dict = generate_histograms(&records, &1.0).unwrap();
+        let extent = GridExtent {
+            west: 0.0,
+            east: 3.0,
+            south: -1.0,
+            north: 1.0,
+        };
+
+        let cells = |order| -> Vec<(f64, f64, i64)> {
+            fill_grid(calc_stats(&grid_dict).unwrap(), 1.0, &extent, order)
+                .iter()
+                .map(|r| (r.longitude, r.latitude, r.counts))
+                .collect()
+        };
+        assert_eq!(
+            cells(GridOrder::RowMajor),
+            [
+                (0.5, 0.5, 0),
+                (1.5, 0.5, 0),
+                (2.5, 0.5, 0),
+                (0.5, -0.5, 0),
+                (1.5, -0.5, 1),
+                (2.5, -0.5, 0)
+            ]
+        );
+        assert_eq!(
+            cells(GridOrder::ColumnMajor),
+            [
+                (0.5, 0.5, 0),
+                (0.5, -0.5, 0),
+                (1.5, 0.5, 0),
+                (1.5, -0.5, 1),
+                (2.5, 0.5, 0),
+                (2.5, -0.5, 0)
+            ]
+        );
+    }
+
+    #[test]
+    /// The sort orders compare the coordinates numerically, whatever their sign or width.
+    fn sort_orders() {
+        let records: Vec<ReadRecord> = [
+            (143.5, -32.5, 1000),
+            (-3.5, -2.5, 3000),
+            (-3.5, -2.5, 1000),
+            (143.5, 5.5, 1500),
+            (-120.5, -2.5, 1000),
+            (-120.5, 45.5, 500),
+        ]
+        .iter()
+        .map(|&(longitude, latitude, height)| ReadRecord {
+            longitude,
+            latitude,
+            height,
+            footprint: None,
+        })
+        .collect();
+        let grid_dict = generate_histograms(&records, &1.0).unwrap();
+
+        let sorted = |order| -> Vec<(f64, f64)> {
+            let mut csv_records = calc_stats(&grid_dict).unwrap();
+            sort_records(&mut csv_records, order);
+            csv_records
+                .iter()
+                .map(|r| (r.longitude, r.latitude))
+                .collect()
+        };
+        assert_eq!(
+            sorted(SortOrder::LatMajor),
+            [
+                (-120.5, 45.5),
+                (143.5, 5.5),
+                (-120.5, -2.5),
+                (-3.5, -2.5),
+                (143.5, -32.5)
+            ]
+        );
+        assert_eq!(
+            sorted(SortOrder::LonMajor),
+            [
+                (-120.5, 45.5),
+                (-120.5, -2.5),
+                (-3.5, -2.5),
+                (143.5, 5.5),
+                (143.5, -32.5)
+            ]
+        );
+        assert_eq!(
+            sorted(SortOrder::Counts),
+            [
+                (-3.5, -2.5),
+                (-120.5, 45.5),
+                (143.5, 5.5),
+                (-120.5, -2.5),
+                (143.5, -32.5)
+            ]
+        );
+        assert_eq!(
+            sorted(SortOrder::MeanHeight),
+            [
+                (-3.5, -2.5),
+                (143.5, 5.5),
+                (-120.5, -2.5),
+                (143.5, -32.5),
+                (-120.5, 45.5)
+            ]
+        );
+    }
 }
@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+
+use crate::compute::micro_degrees;
+use crate::outputs::WriteRecord;
+
+/// The CellComparison struct holds a cell of the base and new grids, either of
+/// which may have no observations in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellComparison {
+    pub longitude: f64,
+    pub latitude: f64,
+    pub base_counts: i64,
+    pub new_counts: i64,
+    pub base_mean: f64,
+    pub new_mean: f64,
+}
+
+impl CellComparison {
+    pub fn count_change(&self) -> i64 {
+        self.new_counts - self.base_counts
+    }
+
+    /// The new mean height less the base mean height.
+    pub fn difference(&self) -> f64 {
+        self.new_mean - self.base_mean
+    }
+
+    /// The new mean height over the base mean height.
+    pub fn ratio(&self) -> f64 {
+        self.new_mean / self.base_mean
+    }
+}
+
+/// The ComparisonSummary struct holds the metrics of the differences of the
+/// mean heights, over the cells with a mean height in both grids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparisonSummary {
+    pub compared_cells: usize,
+    pub base_only_cells: usize,
+    pub new_only_cells: usize,
+    pub count_change: i64,
+    /// The mean difference.
+    pub bias: f64,
+    pub rmse: f64,
+    /// The Pearson correlation of the mean heights.
+    pub correlation: f64,
+    pub tolerance: f64,
+    /// The fraction of the compared cells whose difference exceeds the tolerance.
+    pub changed_fraction: f64,
+}
+
+/// Whether a cell of a (possibly dense) grid holds any observations.
+fn is_populated(record: &WriteRecord) -> bool {
+    record.counts > 0 || record.mean_height.is_finite()
+}
+
+/// This function matches up the populated cells of the base and new grids by
+/// their centers, in lon-major order. Cells populated in only one of the grids
+/// have no observations in the other.
+pub fn compare_cells(base: &[WriteRecord], new: &[WriteRecord]) -> Vec<CellComparison> {
+    let key = |record: &WriteRecord| {
+        (
+            micro_degrees(record.longitude),
+            -micro_degrees(record.latitude),
+        )
+    };
+    let empty = |record: &WriteRecord| CellComparison {
+        longitude: record.longitude,
+        latitude: record.latitude,
+        base_counts: 0,
+        new_counts: 0,
+        base_mean: f64::NAN,
+        new_mean: f64::NAN,
+    };
+
+    let mut cells: BTreeMap<(i64, i64), CellComparison> = BTreeMap::new();
+    for record in base.iter().filter(|record| is_populated(record)) {
+        let cell = cells.entry(key(record)).or_insert_with(|| empty(record));
+        cell.base_counts = record.counts;
+        cell.base_mean = record.mean_height;
+    }
+    for record in new.iter().filter(|record| is_populated(record)) {
+        let cell = cells.entry(key(record)).or_insert_with(|| empty(record));
+        cell.new_counts = record.counts;
+        cell.new_mean = record.mean_height;
+    }
+    cells.into_values().collect()
+}
+
+/// This function computes the metrics of the compared cells. A cell has changed
+/// when its mean height differs by more than the tolerance.
+pub fn summarize_comparison(cells: &[CellComparison], tolerance: f64) -> ComparisonSummary {
+    let pairs: Vec<(f64, f64)> = cells
+        .iter()
+        .filter(|cell| cell.base_mean.is_finite() && cell.new_mean.is_finite())
+        .map(|cell| (cell.base_mean, cell.new_mean))
+        .collect();
+    let n = pairs.len() as f64;
+    let mean = |values: &mut dyn Iterator<Item = f64>| values.sum::<f64>() / n;
+
+    let bias = mean(&mut pairs.iter().map(|(base, new)| new - base));
+    let rmse = mean(&mut pairs.iter().map(|(base, new)| (new - base).powi(2))).sqrt();
+    let base_mean = mean(&mut pairs.iter().map(|(base, _)| *base));
+    let new_mean = mean(&mut pairs.iter().map(|(_, new)| *new));
+    let covariance = mean(
+        &mut pairs
+            .iter()
+            .map(|(base, new)| (base - base_mean) * (new - new_mean)),
+    );
+    let base_variance = mean(&mut pairs.iter().map(|(base, _)| (base - base_mean).powi(2)));
+    let new_variance = mean(&mut pairs.iter().map(|(_, new)| (new - new_mean).powi(2)));
+    let changed = pairs
+        .iter()
+        .filter(|(base, new)| (new - base).abs() > tolerance)
+        .count();
+
+    ComparisonSummary {
+        compared_cells: pairs.len(),
+        base_only_cells: cells
+            .iter()
+            .filter(|cell| cell.new_mean.is_nan() && cell.new_counts == 0)
+            .count(),
+        new_only_cells: cells
+            .iter()
+            .filter(|cell| cell.base_mean.is_nan() && cell.base_counts == 0)
+            .count(),
+        count_change: cells.iter().map(CellComparison::count_change).sum(),
+        bias,
+        rmse,
+        correlation: covariance / (base_variance * new_variance).sqrt(),
+        tolerance,
+        changed_fraction: changed as f64 / n,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compute::compare::{compare_cells, summarize_comparison};
+    use crate::outputs::WriteRecord;
+
+    fn cell(longitude: f64, latitude: f64, counts: i64, mean_height: f64) -> WriteRecord {
+        WriteRecord {
+            longitude,
+            latitude,
+            counts,
+            sum_heights: 0,
+            sum_squared_heights: 0,
+            mean_height,
+            stdev_height: f64::NAN,
+            cell_area: f64::NAN,
+        }
+    }
+
+    #[test]
+    /// The cells are matched by their centers, and the metrics taken over those in both grids.
+    fn compared_grids() {
+        let base = [
+            cell(0.5, 0.5, 2, 1000.0),
+            cell(1.5, 0.5, 4, 2000.0),
+            cell(2.5, 0.5, 1, 3000.0),
+            cell(3.5, 0.5, 0, f64::NAN),
+        ];
+        let new = [
+            cell(1.5, 0.5, 5, 2200.0),
+            cell(0.5, 0.5, 2, 1000.0),
+            cell(2.5, 0.5, 3, 3200.0),
+            cell(4.5, 0.5, 1, 500.0),
+        ];
+        let cells = compare_cells(&base, &new);
+        assert_eq!(cells.len(), 4);
+        assert_eq!((cells[1].count_change(), cells[1].difference()), (1, 200.0));
+        assert_eq!(cells[1].ratio(), 1.1);
+        assert_eq!(cells[3].base_counts, 0);
+        assert!(cells[3].difference().is_nan());
+
+        let summary = summarize_comparison(&cells, 100.0);
+        assert_eq!(summary.compared_cells, 3);
+        assert_eq!((summary.base_only_cells, summary.new_only_cells), (0, 1));
+        assert_eq!(summary.count_change, 4);
+        assert!((summary.bias - 400.0 / 3.0).abs() < 1e-9);
+        assert!((summary.rmse - (80000.0f64 / 3.0).sqrt()).abs() < 1e-9);
+        assert!(summary.correlation > 0.99 && summary.correlation <= 1.0);
+        assert!((summary.changed_fraction - 2.0 / 3.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,511 @@
+use std::{
+    f64::consts::{FRAC_PI_2, FRAC_PI_4},
+    fmt,
+};
+
+use crate::compute::remap::EARTH_RADIUS;
+
+/// Radius of the sphere of the MODIS sinusoidal grid, in km.
+const SINUSOIDAL_RADIUS: f64 = 6371.007181;
+
+/// Semi-major axis and eccentricity of the WGS84 ellipsoid, used by EASE-Grid 2.0.
+const WGS84_A: f64 = 6378.137;
+const WGS84_E: f64 = 0.081819190842622;
+
+/// Polar semi-axis of the GRS80 ellipsoid of the geostationary fixed grids, and
+/// the distance of the satellite from the center of the Earth, in km.
+const GRS80_B: f64 = 6356.75231414;
+const GEO_ORBIT_RADIUS: f64 = 42164.0;
+
+/// Standard parallel of the global EASE-Grid 2.0, in degrees.
+const EASE2_STANDARD_PARALLEL: f64 = 30.0;
+
+/// Each side of a cell of a conformal grid is split into this many parts when
+/// integrating its area.
+const AREA_SUBDIVISIONS: usize = 4;
+
+/// The grid the observations are binned into. The lon/lat grid has square cells
+/// of `grid_size` degrees, and the projected grids square cells of `resolution`
+/// km on the projection plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grid {
+    LonLat {
+        grid_size: f64,
+    },
+    /// The global EASE-Grid 2.0, a cylindrical equal-area projection of the
+    /// WGS84 ellipsoid.
+    Ease2 {
+        resolution: f64,
+    },
+    /// The sinusoidal equal-area projection of the MODIS land grids.
+    Sinusoidal {
+        resolution: f64,
+    },
+    /// A polar stereographic projection of one hemisphere, with true scale at
+    /// `true_scale_lat` and `central_lon` pointing down from the pole.
+    PolarStereographic {
+        resolution: f64,
+        true_scale_lat: f64,
+        central_lon: f64,
+    },
+    /// A Lambert conformal conic projection with the standard parallels
+    /// `lat_1` and `lat_2`, and the origin at (`lon_0`, `lat_0`).
+    LambertConformal {
+        resolution: f64,
+        lat_1: f64,
+        lat_2: f64,
+        lat_0: f64,
+        lon_0: f64,
+    },
+    /// The fixed grid of a geostationary imager at `sub_lon`, in scan and
+    /// elevation angles, with cells of `resolution` km at the sub-satellite
+    /// point. The AHI scans in elevation (`sweep_x` false), the ABI in azimuth.
+    Geostationary {
+        resolution: f64,
+        sub_lon: f64,
+        sweep_x: bool,
+    },
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Grid::LonLat { grid_size } => write!(f, "lon/lat {grid_size} degree"),
+            Grid::Ease2 { resolution } => write!(f, "EASE-Grid 2.0 {resolution} km"),
+            Grid::Sinusoidal { resolution } => write!(f, "sinusoidal {resolution} km"),
+            Grid::PolarStereographic {
+                resolution,
+                true_scale_lat,
+                central_lon,
+            } => write!(
+                f,
+                "polar stereographic {resolution} km (true scale at {true_scale_lat}, central longitude {central_lon})"
+            ),
+            Grid::LambertConformal {
+                resolution,
+                lat_1,
+                lat_2,
+                lat_0,
+                lon_0,
+            } => write!(
+                f,
+                "Lambert conformal {resolution} km (standard parallels {lat_1} and {lat_2}, origin {lon_0},{lat_0})"
+            ),
+            Grid::Geostationary {
+                resolution,
+                sub_lon,
+                sweep_x,
+            } => write!(
+                f,
+                "geostationary fixed grid {resolution} km (sub-satellite longitude {sub_lon}, sweep {})",
+                if *sweep_x { "x" } else { "y" }
+            ),
+        }
+    }
+}
+
+/// The authalic function q of the latitude on the ellipsoid.
+fn authalic_q(sin_lat: f64) -> f64 {
+    let e = WGS84_E;
+    (1.0 - e * e)
+        * (sin_lat / (1.0 - (e * sin_lat).powi(2))
+            - (1.0 / (2.0 * e)) * ((1.0 - e * sin_lat) / (1.0 + e * sin_lat)).ln())
+}
+
+/// Scale of the global EASE-Grid 2.0 along its standard parallel.
+fn ease2_k0() -> f64 {
+    let sin_lat = EASE2_STANDARD_PARALLEL.to_radians().sin();
+    EASE2_STANDARD_PARALLEL.to_radians().cos() / (1.0 - (WGS84_E * sin_lat).powi(2)).sqrt()
+}
+
+/// The cone constant, and the radius scale F, of a Lambert conformal projection.
+fn lambert_cone(lat_1: f64, lat_2: f64) -> (f64, f64) {
+    let (lat_1, lat_2) = (lat_1.to_radians(), lat_2.to_radians());
+    let t = |lat: f64| (FRAC_PI_4 + lat / 2.0).tan();
+    let n = if (lat_1 - lat_2).abs() < 1.0e-10 {
+        lat_1.sin()
+    } else {
+        (lat_1.cos() / lat_2.cos()).ln() / (t(lat_2) / t(lat_1)).ln()
+    };
+    (n, lat_1.cos() * t(lat_1).powf(n) / n)
+}
+
+impl Grid {
+    /// The size of the cells, in degrees for the lon/lat grid or km otherwise.
+    pub fn resolution(&self) -> f64 {
+        match self {
+            Grid::LonLat { grid_size } => *grid_size,
+            Grid::Ease2 { resolution }
+            | Grid::Sinusoidal { resolution }
+            | Grid::PolarStereographic { resolution, .. }
+            | Grid::LambertConformal { resolution, .. }
+            | Grid::Geostationary { resolution, .. } => *resolution,
+        }
+    }
+
+    /// The size of the cells on the grid plane, which is in radians of scan
+    /// angle for the geostationary grid.
+    fn step(&self) -> f64 {
+        match self {
+            Grid::Geostationary { resolution, .. } => resolution / (GEO_ORBIT_RADIUS - WGS84_A),
+            _ => self.resolution(),
+        }
+    }
+
+    pub fn is_lon_lat(&self) -> bool {
+        matches!(self, Grid::LonLat { .. })
+    }
+
+    /// Projects the point onto the grid plane, or None if it lies outside the
+    /// domain of the projection.
+    pub fn forward(&self, longitude: f64, latitude: f64) -> Option<(f64, f64)> {
+        let (lon, lat) = (longitude.to_radians(), latitude.to_radians());
+        match self {
+            Grid::LonLat { .. } => Some((longitude, latitude)),
+            Grid::Ease2 { .. } => {
+                let k0 = ease2_k0();
+                Some((
+                    WGS84_A * k0 * lon,
+                    WGS84_A * authalic_q(lat.sin()) / (2.0 * k0),
+                ))
+            }
+            Grid::Sinusoidal { .. } => {
+                Some((SINUSOIDAL_RADIUS * lon * lat.cos(), SINUSOIDAL_RADIUS * lat))
+            }
+            Grid::PolarStereographic {
+                true_scale_lat,
+                central_lon,
+                ..
+            } => {
+                // The southern hemisphere is handled as a mirror image of the northern
+                let sign = true_scale_lat.signum();
+                if latitude * sign < 0.0 {
+                    return None;
+                }
+                let k0 = (1.0 + (true_scale_lat * sign).to_radians().sin()) / 2.0;
+                let rho = 2.0 * EARTH_RADIUS * k0 * (FRAC_PI_4 - lat * sign / 2.0).tan();
+                let d_lon = lon - central_lon.to_radians();
+                Some((rho * d_lon.sin(), -sign * rho * d_lon.cos()))
+            }
+            Grid::LambertConformal {
+                lat_1,
+                lat_2,
+                lat_0,
+                lon_0,
+                ..
+            } => {
+                let (n, f) = lambert_cone(*lat_1, *lat_2);
+                if (latitude.abs() - 90.0).abs() < 1.0e-9 && latitude * n < 0.0 {
+                    return None;
+                }
+                let rho = |lat: f64| EARTH_RADIUS * f / (FRAC_PI_4 + lat / 2.0).tan().powf(n);
+                let theta = n * (lon - lon_0.to_radians());
+                let rho_0 = rho(lat_0.to_radians());
+                Some((rho(lat) * theta.sin(), rho_0 - rho(lat) * theta.cos()))
+            }
+            Grid::Geostationary {
+                sub_lon, sweep_x, ..
+            } => {
+                // The geocentric position of the point, relative to the sub-satellite point
+                let lat_c = ((GRS80_B / WGS84_A).powi(2) * lat.tan()).atan();
+                let e2 = 1.0 - (GRS80_B / WGS84_A).powi(2);
+                let r = GRS80_B / (1.0 - e2 * lat_c.cos().powi(2)).sqrt();
+                let d_lon = lon - sub_lon.to_radians();
+                let (v_x, v_y, v_z) = (
+                    r * lat_c.cos() * d_lon.cos(),
+                    r * lat_c.cos() * d_lon.sin(),
+                    r * lat_c.sin(),
+                );
+
+                // Points on the far side of the Earth can't be seen
+                let to_satellite = GEO_ORBIT_RADIUS - v_x;
+                if to_satellite * v_x - v_y * v_y - v_z * v_z * (WGS84_A / GRS80_B).powi(2) < 0.0 {
+                    return None;
+                }
+                match sweep_x {
+                    true => Some((
+                        (v_y / v_z.hypot(to_satellite)).atan(),
+                        (v_z / to_satellite).atan(),
+                    )),
+                    false => Some((
+                        (v_y / to_satellite).atan(),
+                        (v_z / v_y.hypot(to_satellite)).atan(),
+                    )),
+                }
+            }
+        }
+    }
+
+    /// The inverse of `forward`, giving the longitude and latitude of a point on
+    /// the grid plane.
+    pub fn inverse(&self, x: f64, y: f64) -> (f64, f64) {
+        let (lon, lat) = match self {
+            Grid::LonLat { .. } => return (x, y),
+            Grid::Ease2 { .. } => {
+                let k0 = ease2_k0();
+                let e2 = WGS84_E * WGS84_E;
+                let q_pole = authalic_q(1.0);
+                let beta = (2.0 * y * k0 / (WGS84_A * q_pole)).clamp(-1.0, 1.0).asin();
+                let lat = beta
+                    + (e2 / 3.0 + 31.0 * e2 * e2 / 180.0 + 517.0 * e2 * e2 * e2 / 5040.0)
+                        * (2.0 * beta).sin()
+                    + (23.0 * e2 * e2 / 360.0 + 251.0 * e2 * e2 * e2 / 3780.0) * (4.0 * beta).sin()
+                    + (761.0 * e2 * e2 * e2 / 45360.0) * (6.0 * beta).sin();
+                (x / (WGS84_A * k0), lat)
+            }
+            Grid::Sinusoidal { .. } => {
+                let lat = y / SINUSOIDAL_RADIUS;
+                (x / (SINUSOIDAL_RADIUS * lat.cos().max(1.0e-12)), lat)
+            }
+            Grid::PolarStereographic {
+                true_scale_lat,
+                central_lon,
+                ..
+            } => {
+                let sign = true_scale_lat.signum();
+                let k0 = (1.0 + (true_scale_lat * sign).to_radians().sin()) / 2.0;
+                let rho = x.hypot(y);
+                let lat = sign * (FRAC_PI_2 - 2.0 * (rho / (2.0 * EARTH_RADIUS * k0)).atan());
+                (central_lon.to_radians() + x.atan2(-sign * y), lat)
+            }
+            Grid::LambertConformal {
+                lat_1,
+                lat_2,
+                lat_0,
+                lon_0,
+                ..
+            } => {
+                let (n, f) = lambert_cone(*lat_1, *lat_2);
+                let rho_0 = EARTH_RADIUS * f / (FRAC_PI_4 + lat_0.to_radians() / 2.0).tan().powf(n);
+                let sign = n.signum();
+                let rho = sign * x.hypot(rho_0 - y);
+                let theta = (sign * x).atan2(sign * (rho_0 - y));
+                let lat = 2.0 * (EARTH_RADIUS * f / rho).powf(1.0 / n).atan() - FRAC_PI_2;
+                (lon_0.to_radians() + theta / n, lat)
+            }
+            Grid::Geostationary {
+                sub_lon, sweep_x, ..
+            } => {
+                // Follow the line of sight from the satellite down to the ellipsoid
+                let (d_y, d_z) = match sweep_x {
+                    true => (x.tan() / y.cos(), y.tan()),
+                    false => (x.tan(), y.tan() / x.cos()),
+                };
+                let flattening = (WGS84_A / GRS80_B).powi(2);
+                let a = 1.0 + d_y * d_y + flattening * d_z * d_z;
+                let h = GEO_ORBIT_RADIUS;
+                let discriminant = h * h - a * (h * h - WGS84_A * WGS84_A);
+                if discriminant < 0.0 {
+                    return (f64::NAN, f64::NAN);
+                }
+                let t = (h - discriminant.sqrt()) / a;
+                let (v_x, v_y, v_z) = (h - t, t * d_y, t * d_z);
+                (
+                    sub_lon.to_radians() + v_y.atan2(v_x),
+                    (flattening * v_z / v_x.hypot(v_y)).atan(),
+                )
+            }
+        };
+        // Wrap the longitude into [-180, 180)
+        let lon = (lon.to_degrees() + 180.0).rem_euclid(360.0) - 180.0;
+        (lon, lat.to_degrees())
+    }
+
+    /// The (column, row) of the cell holding the point, or None if it lies
+    /// outside the domain of the projection.
+    pub fn cell_index(&self, longitude: f64, latitude: f64) -> Option<(i64, i64)> {
+        let step = self.step();
+        let (x, y) = self.forward(longitude, latitude)?;
+        Some(((x / step).floor() as i64, (y / step).floor() as i64))
+    }
+
+    /// The longitude and latitude of the center of the cell.
+    pub fn cell_center(&self, column: i64, row: i64) -> (f64, f64) {
+        let step = self.step();
+        let x = step * column as f64 + step / 2.0;
+        let y = step * row as f64 + step / 2.0;
+        self.inverse(x, y)
+    }
+
+    /// The longitude and latitude of the corners of the cell, anticlockwise in the
+    /// plane of the grid from its south-west corner. Corners outside the domain of
+    /// the projection are NaN.
+    pub fn cell_corners(&self, column: i64, row: i64) -> [(f64, f64); 4] {
+        let step = self.step();
+        let (west, south) = (step * column as f64, step * row as f64);
+        [
+            self.inverse(west, south),
+            self.inverse(west + step, south),
+            self.inverse(west + step, south + step),
+            self.inverse(west, south + step),
+        ]
+    }
+
+    /// The scale factor of a conformal projection at the latitude.
+    fn scale_factor(&self, latitude: f64) -> f64 {
+        let lat = latitude.to_radians();
+        match self {
+            Grid::PolarStereographic { true_scale_lat, .. } => {
+                let sign = true_scale_lat.signum();
+                (1.0 + (true_scale_lat * sign).to_radians().sin()) / (1.0 + lat.sin() * sign)
+            }
+            Grid::LambertConformal { lat_1, lat_2, .. } => {
+                let (n, f) = lambert_cone(*lat_1, *lat_2);
+                let rho = EARTH_RADIUS * f / (FRAC_PI_4 + lat / 2.0).tan().powf(n);
+                n * rho / (EARTH_RADIUS * lat.cos())
+            }
+            Grid::LonLat { .. }
+            | Grid::Ease2 { .. }
+            | Grid::Sinusoidal { .. }
+            | Grid::Geostationary { .. } => 1.0,
+        }
+    }
+
+    /// The area of the cell on the Earth, in km². This is exact for the lon/lat
+    /// and equal-area grids, and integrated numerically for the conformal grids.
+    pub fn cell_area(&self, column: i64, row: i64) -> f64 {
+        let resolution = self.resolution();
+        match self {
+            Grid::LonLat { grid_size } => {
+                let south = (grid_size * row as f64).max(-90.0).to_radians();
+                let north = (grid_size * (row + 1) as f64).min(90.0).to_radians();
+                EARTH_RADIUS.powi(2) * grid_size.to_radians() * (north.sin() - south.sin())
+            }
+            Grid::Ease2 { .. } | Grid::Sinusoidal { .. } => resolution * resolution,
+            Grid::PolarStereographic { .. } | Grid::LambertConformal { .. } => {
+                let step = resolution / AREA_SUBDIVISIONS as f64;
+                let mut area = 0.0;
+                for i in 0..AREA_SUBDIVISIONS {
+                    for j in 0..AREA_SUBDIVISIONS {
+                        let x = resolution * column as f64 + step * (i as f64 + 0.5);
+                        let y = resolution * row as f64 + step * (j as f64 + 0.5);
+                        let (_, latitude) = self.inverse(x, y);
+                        area += step * step / self.scale_factor(latitude).powi(2);
+                    }
+                }
+                area
+            }
+            Grid::Geostationary { .. } => {
+                // The ground area of each part of the cell, from its edges on the ground
+                let step = self.step() / AREA_SUBDIVISIONS as f64;
+                let mut area = 0.0;
+                for i in 0..AREA_SUBDIVISIONS {
+                    for j in 0..AREA_SUBDIVISIONS {
+                        let x = self.step() * column as f64 + step * i as f64;
+                        let y = self.step() * row as f64 + step * j as f64;
+                        let (lon, lat) = self.inverse(x, y);
+                        let to_ground = |(lon_2, lat_2): (f64, f64)| {
+                            let d_lon = (lon_2 - lon + 540.0).rem_euclid(360.0) - 180.0;
+                            (
+                                EARTH_RADIUS * lat.to_radians().cos() * d_lon.to_radians(),
+                                EARTH_RADIUS * (lat_2 - lat).to_radians(),
+                            )
+                        };
+                        let (east_1, north_1) = to_ground(self.inverse(x + step, y));
+                        let (east_2, north_2) = to_ground(self.inverse(x, y + step));
+                        area += (east_1 * north_2 - east_2 * north_1).abs();
+                    }
+                }
+                area
+            }
+        }
+    }
+
+    /// The area of the cell holding the point, in km².
+    pub fn cell_area_at(&self, longitude: f64, latitude: f64) -> f64 {
+        match self.cell_index(longitude, latitude) {
+            Some((column, row)) => self.cell_area(column, row),
+            None => f64::NAN,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compute::grids::Grid;
+
+    const GRIDS: [Grid; 8] = [
+        Grid::LonLat { grid_size: 0.25 },
+        Grid::Ease2 { resolution: 25.0 },
+        Grid::Sinusoidal { resolution: 10.0 },
+        Grid::PolarStereographic {
+            resolution: 25.0,
+            true_scale_lat: 70.0,
+            central_lon: -45.0,
+        },
+        Grid::PolarStereographic {
+            resolution: 25.0,
+            true_scale_lat: -71.0,
+            central_lon: 0.0,
+        },
+        Grid::LambertConformal {
+            resolution: 12.0,
+            lat_1: 33.0,
+            lat_2: 45.0,
+            lat_0: 40.0,
+            lon_0: -97.0,
+        },
+        Grid::Geostationary {
+            resolution: 2.0,
+            sub_lon: 140.7,
+            sweep_x: false,
+        },
+        Grid::Geostationary {
+            resolution: 2.0,
+            sub_lon: -75.2,
+            sweep_x: true,
+        },
+    ];
+
+    #[test]
+    /// Each grid maps points to cells and back, with the cell center in its own cell.
+    fn round_trips() {
+        for grid in GRIDS {
+            for (lon, lat) in [(-100.3, 40.7), (12.5, 75.2), (150.1, -75.4), (-179.9, 5.0)] {
+                let Some((x, y)) = grid.forward(lon, lat) else {
+                    continue;
+                };
+                let (lon_2, lat_2) = grid.inverse(x, y);
+                assert!((lon - lon_2).abs() < 1.0e-6, "{grid}: {lon} != {lon_2}");
+                assert!((lat - lat_2).abs() < 1.0e-6, "{grid}: {lat} != {lat_2}");
+
+                let (column, row) = grid.cell_index(lon, lat).unwrap();
+                let (lon_center, lat_center) = grid.cell_center(column, row);
+                assert_eq!(grid.cell_index(lon_center, lat_center), Some((column, row)));
+            }
+        }
+        assert_eq!(GRIDS[3].forward(0.0, -10.0), None);
+    }
+
+    #[test]
+    /// The cell areas are those of the equal-area grids, and near the true scale otherwise.
+    fn cell_areas() {
+        // A 1 degree cell at the equator is about 111 km square
+        let area = Grid::LonLat { grid_size: 1.0 }.cell_area(0, 0);
+        assert!((area - 111.19 * 111.19).abs() < 10.0);
+
+        assert_eq!(GRIDS[1].cell_area_at(10.0, 50.0), 625.0);
+
+        // True scale at 70N, so a cell there is the nominal size
+        let (column, row) = GRIDS[3].cell_index(-45.0, 70.0).unwrap();
+        assert!((GRIDS[3].cell_area(column, row) - 625.0).abs() < 1.0);
+        let (column, row) = GRIDS[5].cell_index(-97.0, 33.0).unwrap();
+        assert!((GRIDS[5].cell_area(column, row) - 144.0).abs() < 0.5);
+    }
+
+    #[test]
+    /// The AHI fixed grid has cells of the resolution below the satellite, growing
+    /// towards the edge of the disk, and can't see the far side of the Earth.
+    fn geostationary_grid() {
+        let ahi = GRIDS[6];
+        let (column, row) = ahi.cell_index(140.7, 0.0).unwrap();
+        assert_eq!((column, row), (0, 0));
+        assert!((ahi.cell_area(column, row) - 4.0).abs() < 0.05);
+
+        let (column, row) = ahi.cell_index(140.7, -60.0).unwrap();
+        assert!(ahi.cell_area(column, row) > 10.0);
+
+        assert_eq!(ahi.forward(-39.3, 0.0), None);
+        assert_eq!(ahi.forward(20.0, 0.0), None);
+    }
+}
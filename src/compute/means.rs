@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+
+use crate::compute::micro_degrees;
+use crate::outputs::WriteRecord;
+
+/// The latitude bands of the regional means, as (name, south, north).
+pub const REGIONS: [(&str, f64, f64); 8] = [
+    ("global", -90.0, 90.0),
+    ("northern-hemisphere", 0.0, 90.0),
+    ("southern-hemisphere", -90.0, 0.0),
+    ("tropics", -30.0, 30.0),
+    ("northern-midlatitudes", 30.0, 60.0),
+    ("southern-midlatitudes", -60.0, -30.0),
+    ("northern-polar", 60.0, 90.0),
+    ("southern-polar", -90.0, -60.0),
+];
+
+/// How each grid cell is weighted in the means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    /// The cosine of the latitude of the cell center.
+    CosLatitude,
+    /// The area of the cell.
+    CellArea,
+}
+
+impl Weighting {
+    pub fn from_name(name: &str) -> Option<Weighting> {
+        match name {
+            "cos-lat" => Some(Weighting::CosLatitude),
+            "area" => Some(Weighting::CellArea),
+            _ => None,
+        }
+    }
+
+    fn weight(&self, record: &WriteRecord) -> f64 {
+        match self {
+            Weighting::CosLatitude => record.latitude.to_radians().cos(),
+            Weighting::CellArea => record.cell_area,
+        }
+    }
+}
+
+/// The tables of means of the grid cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeanTable {
+    /// One mean per row of latitude, from north to south.
+    Zonal,
+    /// One mean per column of longitude, from west to east.
+    Meridional,
+    /// One mean per latitude band of REGIONS.
+    Global,
+}
+
+impl MeanTable {
+    pub fn from_name(name: &str) -> Option<MeanTable> {
+        match name {
+            "zonal" => Some(MeanTable::Zonal),
+            "meridional" => Some(MeanTable::Meridional),
+            "global" => Some(MeanTable::Global),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MeanTable::Zonal => "zonal",
+            MeanTable::Meridional => "meridional",
+            MeanTable::Global => "global",
+        }
+    }
+
+    /// The header of the column labelling the rows of the table.
+    pub fn label_column(&self) -> &'static str {
+        match self {
+            MeanTable::Zonal => "Latitude",
+            MeanTable::Meridional => "Longitude",
+            MeanTable::Global => "Region",
+        }
+    }
+}
+
+/// What a row of a table of means is the mean over.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeanLabel {
+    Coordinate(f64),
+    Region(&'static str),
+}
+
+/// The AreaMean struct holds the weighted mean height of a group of grid cells.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AreaMean {
+    pub label: MeanLabel,
+    /// The number of populated cells in the group.
+    pub cells: usize,
+    /// The number of observations in those cells.
+    pub counts: i64,
+    /// The sum of the weights of those cells.
+    pub weight: f64,
+    /// The mean of the cell mean heights, weighted by the cell weights.
+    pub mean_height: f64,
+}
+
+impl AreaMean {
+    fn new(label: MeanLabel) -> Self {
+        AreaMean {
+            label,
+            cells: 0,
+            counts: 0,
+            weight: 0.0,
+            mean_height: 0.0,
+        }
+    }
+
+    /// Adds the cell to the group, accumulating the weighted sum of the mean heights.
+    fn add(&mut self, record: &WriteRecord, weight: f64) {
+        self.cells += 1;
+        self.counts += record.counts;
+        self.weight += weight;
+        self.mean_height += weight * record.mean_height;
+    }
+
+    /// Turns the weighted sum of the mean heights into the weighted mean.
+    fn finish(mut self) -> Self {
+        self.mean_height = match self.weight > 0.0 {
+            true => self.mean_height / self.weight,
+            false => f64::NAN,
+        };
+        self
+    }
+}
+
+/// This function computes the table of weighted means of the mean heights of
+/// the populated cells. Empty cells, such as those of a full grid, are left out.
+pub fn area_means(
+    csv_records: &[WriteRecord],
+    table: MeanTable,
+    weighting: Weighting,
+) -> Vec<AreaMean> {
+    log::info!(
+        "Calculating the {} means of the grid cells...",
+        table.name()
+    );
+
+    let populated = csv_records
+        .iter()
+        .filter(|record| record.counts > 0 && !record.mean_height.is_nan())
+        .map(|record| (record, weighting.weight(record)));
+
+    let means: Vec<AreaMean> = match table {
+        MeanTable::Zonal | MeanTable::Meridional => {
+            let mut groups: BTreeMap<i64, AreaMean> = BTreeMap::new();
+            for (record, weight) in populated {
+                let coordinate = match table {
+                    MeanTable::Zonal => record.latitude,
+                    _ => record.longitude,
+                };
+                groups
+                    .entry(micro_degrees(coordinate))
+                    .or_insert_with(|| AreaMean::new(MeanLabel::Coordinate(coordinate)))
+                    .add(record, weight);
+            }
+            let means = groups.into_values().map(AreaMean::finish);
+            match table {
+                MeanTable::Zonal => means.rev().collect(),
+                _ => means.collect(),
+            }
+        }
+        MeanTable::Global => {
+            let mut regions: Vec<AreaMean> = REGIONS
+                .iter()
+                .map(|(name, _, _)| AreaMean::new(MeanLabel::Region(name)))
+                .collect();
+            for (record, weight) in populated {
+                for (mean, (_, south, north)) in regions.iter_mut().zip(REGIONS) {
+                    // The equator belongs to the northern bands
+                    if record.latitude >= south && (record.latitude < north || north == 90.0) {
+                        mean.add(record, weight);
+                    }
+                }
+            }
+            regions.into_iter().map(AreaMean::finish).collect()
+        }
+    };
+
+    log::info!("There are {} {} means", means.len(), table.name());
+    means
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compute::means::{area_means, MeanLabel, MeanTable, Weighting};
+    use crate::outputs::WriteRecord;
+
+    fn cell(longitude: f64, latitude: f64, mean_height: f64, cell_area: f64) -> WriteRecord {
+        WriteRecord {
+            longitude,
+            latitude,
+            counts: 2,
+            sum_heights: 0,
+            sum_squared_heights: 0,
+            mean_height,
+            stdev_height: 0.0,
+            cell_area,
+        }
+    }
+
+    #[test]
+    /// The means are weighted by the cell areas, or the cosine of the latitude.
+    fn weighted_means() {
+        let records = vec![
+            cell(0.5, 60.5, 1000.0, 1.0),
+            cell(1.5, 60.5, 2000.0, 3.0),
+            cell(0.5, 0.5, 4000.0, 4.0),
+            cell(1.5, 0.5, f64::NAN, 4.0),
+        ];
+
+        let zonal = area_means(&records, MeanTable::Zonal, Weighting::CellArea);
+        assert_eq!(zonal.len(), 2);
+        assert_eq!(zonal[0].label, MeanLabel::Coordinate(60.5));
+        assert_eq!((zonal[0].cells, zonal[0].counts), (2, 4));
+        assert_eq!(zonal[0].mean_height, 1750.0);
+        assert_eq!(zonal[1].mean_height, 4000.0);
+
+        let meridional = area_means(&records, MeanTable::Meridional, Weighting::CellArea);
+        assert_eq!(meridional[0].label, MeanLabel::Coordinate(0.5));
+        assert_eq!(meridional[0].mean_height, (1000.0 + 4.0 * 4000.0) / 5.0);
+
+        let global = area_means(&records, MeanTable::Global, Weighting::CellArea);
+        assert_eq!(global[0].label, MeanLabel::Region("global"));
+        assert_eq!(global[0].mean_height, (1000.0 + 6000.0 + 16000.0) / 8.0);
+        assert!(global[2].mean_height.is_nan());
+
+        let global = area_means(&records, MeanTable::Global, Weighting::CosLatitude);
+        let weight = 60.5_f64.to_radians().cos();
+        let expected = (3000.0 * weight + 4000.0 * 0.5_f64.to_radians().cos())
+            / (2.0 * weight + 0.5_f64.to_radians().cos());
+        assert!((global[0].mean_height - expected).abs() < 1.0e-9);
+    }
+}
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+
+use crate::inputs::ReadRecord;
+use crate::outputs::WriteRecord;
+
+/// Mean radius of the Earth, in km.
+pub const EARTH_RADIUS: f64 = 6371.0;
+
+/// Length of one degree of latitude, in km.
+pub const KM_PER_DEGREE: f64 = EARTH_RADIUS * std::f64::consts::PI / 180.0;
+
+/// The observations closer than this (in km) to a cell center are given the
+/// weight of an observation at this distance, rather than an infinite one.
+const MIN_DISTANCE: f64 = 1.0e-3;
+
+/// How the observations are mapped onto the grid cells, other than by binning
+/// each one into the cell containing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemapMethod {
+    /// Inverse-distance weighting of the observations within `radius` km of
+    /// the cell center, with weights of 1/distance^power.
+    InverseDistance { radius: f64, power: f64 },
+    /// Gaussian weighting of the observations within `radius` km of the cell
+    /// center, with a standard deviation of `sigma` km.
+    Gaussian { radius: f64, sigma: f64 },
+    /// The observation closest to the cell center, within `radius` km.
+    Nearest { radius: f64 },
+    /// Each observation is spread over the cells its pixel footprint overlaps,
+    /// weighted by the area of the overlap. Observations without a footprint
+    /// are binned into the cell containing them.
+    Footprint,
+}
+
+impl RemapMethod {
+    fn radius(&self) -> f64 {
+        match self {
+            RemapMethod::InverseDistance { radius, .. }
+            | RemapMethod::Gaussian { radius, .. }
+            | RemapMethod::Nearest { radius } => *radius,
+            RemapMethod::Footprint => 0.0,
+        }
+    }
+
+    fn weight(&self, distance: f64) -> f64 {
+        match self {
+            RemapMethod::InverseDistance { power, .. } => {
+                1.0 / distance.max(MIN_DISTANCE).powf(*power)
+            }
+            RemapMethod::Gaussian { sigma, .. } => {
+                (-distance * distance / (2.0 * sigma * sigma)).exp()
+            }
+            RemapMethod::Nearest { .. } | RemapMethod::Footprint => 1.0,
+        }
+    }
+}
+
+/// The RemapCell struct accumulates the observations within reach of a grid cell.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RemapCell {
+    counts: i64,
+    sum_heights: i64,
+    sum_squared_heights: i64,
+    sum_weights: f64,
+    sum_weighted_heights: f64,
+    sum_weighted_squared_heights: f64,
+    /// The distance and height of the closest observation.
+    nearest: Option<(f64, i64)>,
+}
+
+/// The great-circle distance between two points, in km.
+fn distance(lon_1: f64, lat_1: f64, lon_2: f64, lat_2: f64) -> f64 {
+    let (lat_1, lat_2) = (lat_1.to_radians(), lat_2.to_radians());
+    let d_lat = lat_2 - lat_1;
+    let d_lon = (lon_2 - lon_1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat_1.cos() * lat_2.cos() * (d_lon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS * a.sqrt().min(1.0).asin()
+}
+
+/// The center of the grid cell with the given index.
+fn cell_center(index: i64, grid_size: f64) -> f64 {
+    grid_size * index as f64 + grid_size / 2.0
+}
+
+impl RemapCell {
+    fn add(&mut self, height: i64, weight: f64, distance: f64) {
+        self.counts += 1;
+        self.sum_heights += height;
+        self.sum_squared_heights += height * height;
+        self.sum_weights += weight;
+        self.sum_weighted_heights += weight * height as f64;
+        self.sum_weighted_squared_heights += weight * (height * height) as f64;
+        if self.nearest.is_none_or(|(nearest, _)| distance < nearest) {
+            self.nearest = Some((distance, height));
+        }
+    }
+}
+
+/// Wraps the index of a column past the antimeridian back into [-180, 180).
+fn wrap_column(column: i64, grid_size: f64, num_columns: i64) -> i64 {
+    match cell_center(column, grid_size) {
+        c if c >= 180.0 => column - num_columns,
+        c if c < -180.0 => column + num_columns,
+        _ => column,
+    }
+}
+
+/// This function spreads a record over the grid cells its footprint overlaps, taken
+/// as a lon/lat rectangle, with weights of the fraction of the footprint in each.
+fn spread_record(
+    grid_cells: &mut HashMap<(i64, i64), RemapCell>,
+    record: &ReadRecord,
+    grid_size: f64,
+    num_columns: i64,
+) {
+    let (half_lon, half_lat) = match record.footprint {
+        Some(footprint) => {
+            let cos_lat = record.latitude.to_radians().cos().max(1.0e-6);
+            (
+                (footprint.along_scan / 2.0 / (KM_PER_DEGREE * cos_lat)).min(180.0),
+                footprint.along_track / 2.0 / KM_PER_DEGREE,
+            )
+        }
+        None => (0.0, 0.0),
+    };
+    let (west, east) = (record.longitude - half_lon, record.longitude + half_lon);
+    let (south, north) = (
+        (record.latitude - half_lat).max(-90.0),
+        (record.latitude + half_lat).min(90.0),
+    );
+
+    // A point (or a degenerate footprint) goes wholly into the cell containing it
+    if east - west <= 0.0 || north - south <= 0.0 {
+        let column = (record.longitude / grid_size).floor() as i64;
+        let row = (record.latitude / grid_size).floor() as i64;
+        grid_cells
+            .entry((wrap_column(column, grid_size, num_columns), row))
+            .or_default()
+            .add(record.height, 1.0, 0.0);
+        return;
+    }
+
+    let area = (east - west) * (north - south);
+    let overlap = |low: f64, high: f64, index: i64| {
+        let cell_low = grid_size * index as f64;
+        (high.min(cell_low + grid_size) - low.max(cell_low)).max(0.0)
+    };
+    for row in (south / grid_size).floor() as i64..(north / grid_size).ceil() as i64 {
+        let lat_overlap = overlap(south, north, row);
+        for column in (west / grid_size).floor() as i64..(east / grid_size).ceil() as i64 {
+            let weight = overlap(west, east, column) * lat_overlap / area;
+            if weight <= 0.0 {
+                continue;
+            }
+            let distance = distance(
+                record.longitude,
+                record.latitude,
+                cell_center(column, grid_size),
+                cell_center(row, grid_size),
+            );
+            grid_cells
+                .entry((wrap_column(column, grid_size, num_columns), row))
+                .or_default()
+                .add(record.height, weight, distance);
+        }
+    }
+}
+
+/// This function adds each record to every grid cell whose center lies within
+/// the radius of the method (or which its footprint overlaps), so that records read in chunks can be accumulated
+/// into the same grid.
+pub fn remap_records(
+    grid_cells: &mut HashMap<(i64, i64), RemapCell>,
+    csv_records: &[ReadRecord],
+    grid_size: f64,
+    method: &RemapMethod,
+) {
+    log::info!("Remapping the csv records onto the grid...");
+
+    let radius = method.radius();
+    let num_columns = (360.0 / grid_size).round() as i64;
+
+    for record in csv_records {
+        if *method == RemapMethod::Footprint {
+            spread_record(grid_cells, record, grid_size, num_columns);
+            continue;
+        }
+
+        // The cells which may be within reach, widening in longitude towards the poles
+        let lat_reach = radius / KM_PER_DEGREE;
+        let cos_lat = (record.latitude.abs() + lat_reach)
+            .min(90.0)
+            .to_radians()
+            .cos();
+        let lon_reach = match cos_lat > 1.0e-6 {
+            true => (lat_reach / cos_lat).min(180.0),
+            false => 180.0,
+        };
+
+        let south = ((record.latitude - lat_reach).max(-90.0) / grid_size).floor() as i64;
+        let north = ((record.latitude + lat_reach).min(90.0) / grid_size).ceil() as i64;
+        let west = ((record.longitude - lon_reach) / grid_size).floor() as i64;
+        let east = ((record.longitude + lon_reach) / grid_size).ceil() as i64;
+        let east = east.min(west + num_columns);
+
+        for row in south..north {
+            let lat_center = cell_center(row, grid_size);
+            for column in west..east {
+                let lon_center = cell_center(column, grid_size);
+                let distance = distance(record.longitude, record.latitude, lon_center, lat_center);
+                if distance > radius {
+                    continue;
+                }
+
+                let weight = method.weight(distance);
+                grid_cells
+                    .entry((wrap_column(column, grid_size, num_columns), row))
+                    .or_default()
+                    .add(record.height, weight, distance);
+            }
+        }
+    }
+
+    log::info!("Finished remapping the csv records.");
+}
+
+/// This function computes the statistics of each remapped grid cell. The counts and
+/// sums are of the observations within reach of the cell, and the mean and standard
+/// deviation are weighted. For the nearest method these are of the closest
+/// observation alone.
+pub fn calc_stats(
+    grid_cells: &HashMap<(i64, i64), RemapCell>,
+    grid_size: f64,
+    method: &RemapMethod,
+) -> Vec<WriteRecord> {
+    log::info!("Calculating the stats for each remapped grid cell...");
+
+    let csv_records: Vec<WriteRecord> = grid_cells
+        .iter()
+        .map(|((column, row), cell)| {
+            let longitude = cell_center(*column, grid_size);
+            let latitude = cell_center(*row, grid_size);
+            match (method, cell.nearest) {
+                (RemapMethod::Nearest { .. }, Some((_, height))) => WriteRecord {
+                    longitude,
+                    latitude,
+                    counts: 1,
+                    sum_heights: height,
+                    sum_squared_heights: height * height,
+                    mean_height: height as f64,
+                    stdev_height: 0.0,
+                    cell_area: f64::NAN,
+                },
+                _ => {
+                    let mean_height = cell.sum_weighted_heights / cell.sum_weights;
+                    let mom_2 = cell.sum_weighted_squared_heights / cell.sum_weights;
+                    WriteRecord {
+                        longitude,
+                        latitude,
+                        counts: cell.counts,
+                        sum_heights: cell.sum_heights,
+                        sum_squared_heights: cell.sum_squared_heights,
+                        mean_height,
+                        stdev_height: (mom_2 - mean_height * mean_height).max(0.0).sqrt(),
+                        cell_area: f64::NAN,
+                    }
+                }
+            }
+        })
+        .collect();
+
+    log::info!("There are {} remapped grid cells\n", csv_records.len());
+
+    csv_records
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::compute::remap::{calc_stats, distance, remap_records, RemapMethod};
+    use crate::inputs::footprint::Footprint;
+    use crate::ReadRecord;
+
+    fn record(longitude: f64, latitude: f64, height: i64) -> ReadRecord {
+        ReadRecord {
+            longitude,
+            latitude,
+            height,
+            footprint: None,
+        }
+    }
+
+    /// The remapped statistics as (longitude, latitude, counts, mean height).
+    fn remap(records: &[ReadRecord], method: RemapMethod) -> Vec<(f64, f64, i64, f64)> {
+        let mut grid_cells = HashMap::new();
+        remap_records(&mut grid_cells, records, 1.0, &method);
+        let mut cells: Vec<(f64, f64, i64, f64)> = calc_stats(&grid_cells, 1.0, &method)
+            .iter()
+            .map(|r| (r.longitude, r.latitude, r.counts, r.mean_height))
+            .collect();
+        cells.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        cells
+    }
+
+    #[test]
+    /// One degree of latitude is about 111 km.
+    fn great_circle_distance() {
+        assert!((distance(10.0, 0.0, 10.0, 1.0) - 111.19).abs() < 0.01);
+        assert!((distance(179.5, 0.0, -179.5, 0.0) - 111.19).abs() < 0.01);
+    }
+
+    #[test]
+    /// The closer observation gets the larger weight, and the observations reach
+    /// the cells within the radius, across the antimeridian.
+    fn weighted_remapping() {
+        let records = [record(179.9, 0.5, 1000), record(179.2, 0.5, 2000)];
+        let cells = remap(
+            &records,
+            RemapMethod::InverseDistance {
+                radius: 80.0,
+                power: 2.0,
+            },
+        );
+        assert_eq!(cells.len(), 3);
+        assert_eq!(cells[0], (-179.5, 0.5, 1, 1000.0));
+        assert_eq!(cells[1], (178.5, 0.5, 1, 2000.0));
+        assert_eq!((cells[2].0, cells[2].2), (179.5, 2));
+        assert!(cells[2].3 > 1500.0 && cells[2].3 < 2000.0);
+
+        let cells = remap(
+            &records,
+            RemapMethod::Gaussian {
+                radius: 80.0,
+                sigma: 20.0,
+            },
+        );
+        assert!(cells[2].3 > 1500.0 && cells[2].3 < 2000.0);
+    }
+
+    #[test]
+    /// Empty cells within the radius take the closest observation.
+    fn nearest_fill() {
+        let records = [record(1.0, 0.5, 1000), record(2.8, 0.5, 3000)];
+        let cells = remap(&records, RemapMethod::Nearest { radius: 100.0 });
+        assert_eq!(
+            cells,
+            [
+                (0.5, 0.5, 1, 1000.0),
+                (1.5, 0.5, 1, 1000.0),
+                (2.5, 0.5, 1, 3000.0),
+                (3.5, 0.5, 1, 3000.0)
+            ]
+        );
+    }
+
+    #[test]
+    /// A footprint straddling cells is spread over them by overlap area, while a
+    /// point goes into the cell containing it.
+    fn footprint_spreading() {
+        let mut wide = record(1.0, 0.5, 2000);
+        wide.footprint = Some(Footprint {
+            along_scan: 50.0,
+            along_track: 10.0,
+        });
+        let records = [record(0.5, 0.5, 1000), wide];
+
+        let mut grid_cells = HashMap::new();
+        remap_records(&mut grid_cells, &records, 1.0, &RemapMethod::Footprint);
+        let mut cells: Vec<(f64, i64, f64)> = calc_stats(&grid_cells, 1.0, &RemapMethod::Footprint)
+            .iter()
+            .map(|r| (r.longitude, r.counts, r.mean_height))
+            .collect();
+        cells.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        // Half of the wide footprint (weight 0.5) falls in each cell
+        assert_eq!(cells.len(), 2);
+        assert_eq!(cells[0].1, 2);
+        assert!((cells[0].2 - (1000.0 + 0.5 * 2000.0) / 1.5).abs() < 1.0e-6);
+        assert_eq!(cells[1], (1.5, 1, 2000.0));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::inputs::{regions::Regions, ReadRecord};
+use crate::outputs::WriteRecord;
+
+/// The RegionSummary struct holds the statistics of the observations in a region,
+/// and the populated grid cells whose centers lie in it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegionSummary {
+    pub region: String,
+    pub cells: usize,
+    /// Total area of the cells, in km².
+    pub cell_area: f64,
+    pub counts: i64,
+    pub sum_heights: i64,
+    pub sum_squared_heights: i64,
+    pub mean_height: f64,
+    pub stdev_height: f64,
+}
+
+impl RegionSummary {
+    /// The empty summary of each of the regions.
+    pub fn for_regions(regions: &Regions) -> Vec<RegionSummary> {
+        regions
+            .names
+            .iter()
+            .map(|name| RegionSummary {
+                region: name.clone(),
+                cells: 0,
+                cell_area: 0.0,
+                counts: 0,
+                sum_heights: 0,
+                sum_squared_heights: 0,
+                mean_height: f64::NAN,
+                stdev_height: f64::NAN,
+            })
+            .collect()
+    }
+}
+
+/// This function keeps the records lying in any of the regions, adding each to
+/// the summaries of the regions containing it. It returns the number of records
+/// dropped.
+pub fn select_regions(
+    csv_records: &mut Vec<ReadRecord>,
+    regions: &Regions,
+    summaries: &mut [RegionSummary],
+) -> usize {
+    let num_records = csv_records.len();
+    csv_records.retain(|record| {
+        let containing = regions.containing(record.longitude, record.latitude);
+        for index in &containing {
+            let summary = &mut summaries[*index];
+            summary.counts += 1;
+            summary.sum_heights += record.height;
+            summary.sum_squared_heights += record.height * record.height;
+        }
+        !containing.is_empty()
+    });
+    num_records - csv_records.len()
+}
+
+/// This function completes the summaries with the statistics of their records,
+/// and the number and area of the populated grid cells in each region.
+pub fn summarize_regions(
+    summaries: &[RegionSummary],
+    regions: &Regions,
+    csv_records: &[WriteRecord],
+) -> Vec<RegionSummary> {
+    let mut summaries = summaries.to_vec();
+    for record in csv_records.iter().filter(|record| record.counts > 0) {
+        for index in regions.containing(record.longitude, record.latitude) {
+            summaries[index].cells += 1;
+            summaries[index].cell_area += record.cell_area;
+        }
+    }
+
+    for summary in &mut summaries {
+        if summary.counts > 0 {
+            let mom_1 = summary.sum_heights as f64 / summary.counts as f64;
+            let mom_2 = summary.sum_squared_heights as f64 / summary.counts as f64;
+            summary.mean_height = mom_1;
+            summary.stdev_height = (mom_2 - mom_1 * mom_1).max(0.0).sqrt();
+        }
+    }
+    summaries
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compute::grids::Grid;
+    use crate::compute::summaries::{select_regions, summarize_regions, RegionSummary};
+    use crate::compute::{calc_stats, generate_histograms, set_cell_areas};
+    use crate::inputs::{regions::Regions, ReadRecord};
+
+    #[test]
+    /// Only the records in the regions are kept, and summarized over each region.
+    fn region_summaries() {
+        let regions = Regions::from_wkt(
+            "west\tPOLYGON ((0 0, 2 0, 2 2, 0 2, 0 0))\n\
+             all\tPOLYGON ((0 0, 4 0, 4 2, 0 2, 0 0))",
+        )
+        .unwrap();
+        let mut records: Vec<ReadRecord> = [
+            (0.5, 0.5, 10),
+            (1.5, 0.5, 20),
+            (3.5, 1.5, 60),
+            (5.0, 1.0, 99),
+        ]
+        .iter()
+        .map(|(longitude, latitude, height)| ReadRecord {
+            longitude: *longitude,
+            latitude: *latitude,
+            height: *height,
+            footprint: None,
+        })
+        .collect();
+
+        let mut summaries = RegionSummary::for_regions(&regions);
+        assert_eq!(select_regions(&mut records, &regions, &mut summaries), 1);
+        assert_eq!(records.len(), 3);
+
+        let grid = Grid::LonLat { grid_size: 1.0 };
+        let mut cells = calc_stats(&generate_histograms(&records, &1.0).unwrap()).unwrap();
+        set_cell_areas(&mut cells, &grid);
+        let summaries = summarize_regions(&summaries, &regions, &cells);
+
+        assert_eq!((summaries[0].cells, summaries[0].counts), (2, 2));
+        assert_eq!(summaries[0].mean_height, 15.0);
+        assert_eq!(summaries[0].stdev_height, 5.0);
+        assert_eq!((summaries[1].cells, summaries[1].counts), (3, 3));
+        assert_eq!(summaries[1].sum_heights, 90);
+        assert!((summaries[1].cell_area - 3.0 * grid.cell_area(0, 0)).abs() < 10.0);
+    }
+}
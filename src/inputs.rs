@@ -1,5 +1,12 @@
 // inputs.rs
 //
 // This file exposes the contents of the "inputs" module
+pub mod compression; // src/inputs/compression.rs
+pub mod discovery; // src/inputs/discovery.rs
+pub mod footprint; // src/inputs/footprint.rs
+pub mod granules; // src/inputs/granules.rs
+pub mod gridded; // src/inputs/gridded.rs
 pub mod reads; // src/inputs/reads.rs
+pub mod regions; // src/inputs/regions.rs
+pub mod sensors; // src/inputs/sensors.rs
 pub use reads::ReadRecord;
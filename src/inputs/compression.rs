@@ -0,0 +1,263 @@
+use std::{
+    cell::Cell,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+    rc::Rc,
+};
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+/// The path used on the command line for stdin (and stdout).
+pub const STDIO_PATH: &str = "-";
+
+/// Whether the path stands for stdin/stdout rather than a file.
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO_PATH)
+}
+
+/// The extension of the file, in lower case, ahead of any compression extension,
+/// so "out.GeoJSON.gz" has the extension "geojson".
+pub fn uncompressed_extension(path: &Path) -> Option<String> {
+    let uncompressed = match Compression::from_extension(path) {
+        Compression::None => path.to_path_buf(),
+        _ => path.with_extension(""),
+    };
+    uncompressed
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+}
+
+/// The compression formats we can read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 5] = [
+        Compression::None,
+        Compression::Gzip,
+        Compression::Bzip2,
+        Compression::Xz,
+        Compression::Zstd,
+    ];
+
+    /// The file extension of the format, without the leading dot.
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Bzip2 => Some("bz2"),
+            Compression::Xz => Some("xz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+
+    /// The magic bytes which start a file of the format.
+    fn magic(&self) -> &'static [u8] {
+        match self {
+            Compression::None => &[],
+            Compression::Gzip => &[0x1f, 0x8b],
+            Compression::Bzip2 => b"BZh",
+            Compression::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0x00],
+            Compression::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+        }
+    }
+
+    /// The compression format implied by the file extension.
+    pub fn from_extension(path: &Path) -> Compression {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        Compression::ALL
+            .into_iter()
+            .find(|c| c.extension() == Some(extension))
+            .unwrap_or(Compression::None)
+    }
+
+    /// The compression format implied by the first bytes of a file.
+    pub fn from_magic(bytes: &[u8]) -> Compression {
+        Compression::ALL
+            .into_iter()
+            .filter(|c| *c != Compression::None)
+            .find(|c| bytes.starts_with(c.magic()))
+            .unwrap_or(Compression::None)
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Bzip2 => "bzip2",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zstd",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// The CountingReader struct counts the bytes read through it, so that progress
+/// through a compressed file can be measured against its size on disk.
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let num_bytes = self.inner.read(buf)?;
+        self.bytes_read
+            .set(self.bytes_read.get() + num_bytes as u64);
+        Ok(num_bytes)
+    }
+}
+
+/// The Input struct is an open input file (or stdin), decompressed if need be.
+pub struct Input {
+    reader: Box<dyn Read>,
+    bytes_read: Rc<Cell<u64>>,
+    pub compression: Compression,
+}
+
+impl Input {
+    /// Opens the file, or stdin if the path is "-". The compression format is
+    /// detected from the magic bytes at the start of the data.
+    pub fn open(path: &Path) -> Result<Input, Box<dyn Error>> {
+        let bytes_read = Rc::new(Cell::new(0));
+
+        let raw: Box<dyn Read> = if is_stdio(path) {
+            Box::new(io::stdin())
+        } else {
+            Box::new(File::open(path)?)
+        };
+        let mut raw = BufReader::new(CountingReader {
+            inner: raw,
+            bytes_read: Rc::clone(&bytes_read),
+        });
+
+        let compression = Compression::from_magic(raw.fill_buf()?);
+        let from_extension = Compression::from_extension(path);
+        if from_extension != Compression::None && from_extension != compression {
+            log::warn!(
+                "The file {path:?} looks to be {compression} compressed, not {from_extension}."
+            );
+        }
+        if compression != Compression::None {
+            log::debug!("\tDecompressing {path:?} using {compression}");
+        }
+
+        let reader: Box<dyn Read> = match compression {
+            Compression::None => Box::new(raw),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(raw)),
+            Compression::Bzip2 => Box::new(MultiBzDecoder::new(raw)),
+            Compression::Xz => Box::new(XzDecoder::new_multi_decoder(raw)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(raw)?),
+        };
+
+        Ok(Input {
+            reader,
+            bytes_read,
+            compression,
+        })
+    }
+
+    /// A handle on the number of (compressed) bytes read so far, which stays
+    /// usable once the input has been handed to a csv reader.
+    pub fn bytes_read(&self) -> Rc<Cell<u64>> {
+        Rc::clone(&self.bytes_read)
+    }
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        path::PathBuf,
+    };
+
+    use crate::inputs::compression::{Compression, Input};
+
+    const CONTENTS: &str = "100.0,35.2,12345\n101.0,36.2,2345\n";
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::None => data.to_vec(),
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Zstd => zstd::encode_all(data, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    /// Each format is detected from its magic bytes and decompressed.
+    fn read_compressed_inputs() {
+        for compression in Compression::ALL {
+            let extension = compression
+                .extension()
+                .map_or(String::new(), |e| format!(".{e}"));
+            let path = std::env::temp_dir().join(format!(
+                "grid_test_compressed_{}.csv{extension}",
+                std::process::id()
+            ));
+            let compressed = compress(compression, CONTENTS.as_bytes());
+            fs::write(&path, &compressed).unwrap();
+
+            let mut input = Input::open(&path).unwrap();
+            assert_eq!(input.compression, compression);
+            let mut contents = String::new();
+            input.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, CONTENTS);
+            assert_eq!(input.bytes_read().get(), compressed.len() as u64);
+
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    /// The extension gives the expected format.
+    fn compression_from_extension() {
+        assert_eq!(
+            Compression::from_extension(&PathBuf::from("a.csv.gz")),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_extension(&PathBuf::from("a.csv.zst")),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::from_extension(&PathBuf::from("a.csv")),
+            Compression::None
+        );
+    }
+}
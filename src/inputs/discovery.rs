@@ -0,0 +1,362 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use glob::{glob, Pattern};
+use walkdir::WalkDir;
+
+use crate::inputs::compression::is_stdio;
+
+/// The default patterns used to select files when walking a directory.
+pub const DEFAULT_INCLUDE: [&str; 5] = ["*.csv", "*.csv.gz", "*.csv.bz2", "*.csv.xz", "*.csv.zst"];
+
+/// How symbolic links met while walking a directory are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkPolicy {
+    /// Follow links to both files and directories.
+    Follow,
+    /// Keep links to files, but don't descend into linked directories.
+    Files,
+    /// Ignore all links.
+    Skip,
+}
+
+impl SymlinkPolicy {
+    pub fn from_name(name: &str) -> Option<SymlinkPolicy> {
+        match name {
+            "follow" => Some(SymlinkPolicy::Follow),
+            "files" => Some(SymlinkPolicy::Files),
+            "skip" => Some(SymlinkPolicy::Skip),
+            _ => None,
+        }
+    }
+}
+
+/// The DiscoveryOptions struct holds the settings controlling how the
+/// input files are found from the paths given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveryOptions {
+    pub recursive: bool,
+    pub max_depth: Option<usize>,
+    pub include: Vec<Pattern>,
+    pub exclude: Vec<Pattern>,
+    pub symlinks: SymlinkPolicy,
+}
+
+impl Default for DiscoveryOptions {
+    fn default() -> Self {
+        DiscoveryOptions {
+            recursive: false,
+            max_depth: None,
+            include: DEFAULT_INCLUDE
+                .iter()
+                .map(|p| Pattern::new(p).unwrap())
+                .collect(),
+            exclude: Vec::new(),
+            symlinks: SymlinkPolicy::Files,
+        }
+    }
+}
+
+impl DiscoveryOptions {
+    /// The directory depth to walk down to. Without `--recursive` only the
+    /// immediate contents of a directory are used.
+    fn depth(&self) -> usize {
+        match (self.recursive, self.max_depth) {
+            (true, Some(depth)) => depth,
+            (true, None) => usize::MAX,
+            (false, _) => 1,
+        }
+    }
+
+    /// Whether a file found in a directory or by a glob should be used.
+    fn is_selected(&self, path: &Path, from_dir: bool) -> bool {
+        let Some(file_name) = path.file_name().and_then(|f| f.to_str()) else {
+            return false;
+        };
+        let included = !from_dir || self.include.iter().any(|p| p.matches(file_name));
+        let excluded = self.exclude.iter().any(|p| p.matches(file_name));
+        included && !excluded
+    }
+
+    /// Whether a symlink matched by a glob is kept under the policy, as when
+    /// walking a directory. A followed link to a directory is walked.
+    fn keeps_symlink(&self, path: &Path) -> bool {
+        match self.symlinks {
+            SymlinkPolicy::Follow => true,
+            SymlinkPolicy::Files => path.is_file(),
+            SymlinkPolicy::Skip => false,
+        }
+    }
+}
+
+/// Whether the path contains glob metacharacters.
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Walks a directory, returning the selected files.
+fn walk_dir(dir: &Path, options: &DiscoveryOptions) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut files = Vec::new();
+
+    let walker = WalkDir::new(dir)
+        .min_depth(1)
+        .max_depth(options.depth())
+        .follow_links(options.symlinks == SymlinkPolicy::Follow)
+        .sort_by_file_name();
+
+    for entry in walker {
+        // Unreadable directories and symlink loops don't stop the walk
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("Skipping an entry of the directory {dir:?}: {e}");
+                continue;
+            }
+        };
+        let path = entry.path();
+
+        if entry.path_is_symlink() {
+            match options.symlinks {
+                SymlinkPolicy::Skip => {
+                    log::debug!("\tSkipping the symlink {path:?}");
+                    continue;
+                }
+                // Links to directories are not descended into, as walkdir
+                // isn't following links, but links to files are kept.
+                SymlinkPolicy::Files if !path.is_file() => continue,
+                // Followed links to directories are descended into by walkdir,
+                // but aren't inputs themselves.
+                SymlinkPolicy::Follow if !path.is_file() => continue,
+                _ => {}
+            }
+        } else if !entry.file_type().is_file() {
+            continue;
+        }
+
+        if options.is_selected(path, true) {
+            files.push(path.to_path_buf());
+        }
+    }
+
+    Ok(files)
+}
+
+/// Expands the input paths given on the command line into a sorted list of
+/// files. Each input may be a file, a directory or a glob pattern. Files
+/// reached by more than one path (e.g. through a symlink, or listed twice)
+/// are only returned once.
+pub fn discover_files(
+    inputs: &[&PathBuf],
+    options: &DiscoveryOptions,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    log::info!("Discovering the input files...");
+
+    let mut files: Vec<PathBuf> = Vec::new();
+
+    for input in inputs {
+        let input_str = input.to_string_lossy();
+
+        if input.is_file() || is_stdio(input) {
+            files.push(input.to_path_buf());
+        } else if input.is_dir() {
+            log::debug!("\tWalking the directory {input:?}");
+            files.extend(walk_dir(input, options)?);
+        } else if is_glob(&input_str) {
+            log::debug!("\tExpanding the glob {input_str}");
+            for entry in glob(&input_str)? {
+                let path = match entry {
+                    Ok(path) => path,
+                    Err(e) => {
+                        log::warn!("Skipping a match of the glob {input_str}: {e}");
+                        continue;
+                    }
+                };
+                if path.is_symlink() && !options.keeps_symlink(&path) {
+                    log::debug!("\tSkipping the symlink {path:?}");
+                    continue;
+                }
+                if path.is_dir() {
+                    files.extend(walk_dir(&path, options)?);
+                } else if options.is_selected(&path, false) {
+                    files.push(path);
+                }
+            }
+        } else {
+            Err(format!("The input path {input:?} does not exist"))?
+        }
+    }
+
+    // Sort before de-duplicating so the same path is always kept.
+    files.sort();
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    if files.iter().filter(|path| is_stdio(path)).count() > 1 {
+        Err("Stdin ('-') can only be used once as an input")?
+    }
+    files.retain(|path| {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let is_new = seen.insert(canonical);
+        if !is_new {
+            log::debug!("\tSkipping the duplicate input {path:?}");
+        }
+        is_new
+    });
+
+    log::info!("Found {} input files.", files.len());
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use glob::Pattern;
+
+    use crate::inputs::discovery::{discover_files, DiscoveryOptions};
+
+    /// Creates a fresh directory tree for a test:
+    ///   a.csv, b.txt, sub/c.csv, sub/deep/d.csv, sub/e.csv.gz
+    fn make_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("grid_test_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub/deep")).unwrap();
+        for file in [
+            "a.csv",
+            "b.txt",
+            "sub/c.csv",
+            "sub/deep/d.csv",
+            "sub/e.csv.gz",
+        ] {
+            fs::write(root.join(file), "1.0,2.0,3\n").unwrap();
+        }
+        root
+    }
+
+    fn names(root: &PathBuf, files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|f| f.strip_prefix(root).unwrap().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    /// Directories are only descended into with --recursive, down to --max-depth.
+    fn discover_directory_depth() {
+        let root = make_tree("depth");
+        let mut options = DiscoveryOptions::default();
+
+        let files = discover_files(&[&root], &options).unwrap();
+        assert_eq!(names(&root, &files), ["a.csv"]);
+
+        options.recursive = true;
+        let files = discover_files(&[&root], &options).unwrap();
+        assert_eq!(
+            names(&root, &files),
+            ["a.csv", "sub/c.csv", "sub/deep/d.csv", "sub/e.csv.gz"]
+        );
+
+        options.max_depth = Some(2);
+        let files = discover_files(&[&root], &options).unwrap();
+        assert_eq!(names(&root, &files), ["a.csv", "sub/c.csv", "sub/e.csv.gz"]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    /// The include/exclude patterns select files by name.
+    fn discover_include_exclude() {
+        let root = make_tree("patterns");
+        let options = DiscoveryOptions {
+            recursive: true,
+            include: vec![
+                Pattern::new("*.csv").unwrap(),
+                Pattern::new("*.txt").unwrap(),
+            ],
+            exclude: vec![Pattern::new("c.*").unwrap()],
+            ..DiscoveryOptions::default()
+        };
+
+        let files = discover_files(&[&root], &options).unwrap();
+        assert_eq!(names(&root, &files), ["a.csv", "b.txt", "sub/deep/d.csv"]);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    /// Globs are expanded, and files given more than once are de-duplicated.
+    fn discover_glob_and_duplicates() {
+        let root = make_tree("glob");
+        let glob = root.join("**/*.csv");
+        let file = root.join("sub/c.csv");
+
+        let files = discover_files(&[&file, &glob, &file], &DiscoveryOptions::default()).unwrap();
+        assert_eq!(
+            names(&root, &files),
+            ["a.csv", "sub/c.csv", "sub/deep/d.csv"]
+        );
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    /// Followed links to directories are descended into, but aren't inputs.
+    fn discover_followed_directory_link() {
+        use crate::inputs::discovery::SymlinkPolicy;
+
+        let root = make_tree("follow");
+        std::os::unix::fs::symlink(root.join("sub/deep"), root.join("linked.csv")).unwrap();
+        let options = DiscoveryOptions {
+            recursive: true,
+            symlinks: SymlinkPolicy::Follow,
+            ..DiscoveryOptions::default()
+        };
+
+        let files = discover_files(&[&root], &options).unwrap();
+        let names = names(&root, &files);
+        assert!(!names.contains(&"linked.csv".to_string()));
+        assert!(names.contains(&"a.csv".to_string()));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    /// A symlink loop is skipped rather than stopping the walk, and globs keep
+    /// symlinks as the walks do.
+    fn discover_symlink_loop_and_glob() {
+        use crate::inputs::discovery::SymlinkPolicy;
+
+        let root = make_tree("loop");
+        std::os::unix::fs::symlink(&root, root.join("sub/loop")).unwrap();
+        std::os::unix::fs::symlink(root.join("a.csv"), root.join("link.csv")).unwrap();
+        let mut options = DiscoveryOptions {
+            recursive: true,
+            symlinks: SymlinkPolicy::Follow,
+            ..DiscoveryOptions::default()
+        };
+
+        let files = discover_files(&[&root], &options).unwrap();
+        assert!(names(&root, &files).contains(&"sub/deep/d.csv".to_string()));
+
+        let glob = root.join("*.csv");
+        options.symlinks = SymlinkPolicy::Skip;
+        let files = discover_files(&[&glob], &options).unwrap();
+        assert_eq!(names(&root, &files), ["a.csv"]);
+        options.symlinks = SymlinkPolicy::Files;
+        let files = discover_files(&[&glob], &options).unwrap();
+        assert_eq!(names(&root, &files).len(), 1);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    /// A missing input is an error.
+    fn discover_missing_input() {
+        let missing = PathBuf::from("no/such/file.csv");
+        assert!(discover_files(&[&missing], &DiscoveryOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,140 @@
+use std::error::Error;
+
+use csv::StringRecord;
+
+use crate::compute::remap::EARTH_RADIUS;
+
+/// The Footprint struct holds the ground size of a pixel, in km. The scan is
+/// taken to run east-west, as it roughly does for the polar orbiters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Footprint {
+    pub along_scan: f64,
+    pub along_track: f64,
+}
+
+impl Footprint {
+    /// The footprint of a pixel seen at the sensor zenith angle (in degrees), for a
+    /// sensor with the nadir resolution (in km) at the altitude (in km). The pixel
+    /// grows with the slant range along the track, and also with the obliquity of
+    /// the view along the scan. The pixel is unbounded at the horizon, so the zenith
+    /// angle must be at least 0 and below 90 degrees.
+    pub fn from_zenith(
+        zenith: f64,
+        nadir_resolution: f64,
+        altitude: f64,
+    ) -> Result<Footprint, Box<dyn Error>> {
+        if !(0.0..90.0).contains(&zenith) {
+            Err(format!(
+                "Invalid sensor zenith angle {zenith}, expected at least 0 and below 90 degrees"
+            ))?;
+        }
+        let zenith = zenith.to_radians();
+        let orbit_radius = EARTH_RADIUS + altitude;
+        let slant_range = (orbit_radius.powi(2) - (EARTH_RADIUS * zenith.sin()).powi(2)).sqrt()
+            - EARTH_RADIUS * zenith.cos();
+
+        let along_track = nadir_resolution * slant_range / altitude;
+        Ok(Footprint {
+            along_scan: along_track / zenith.cos(),
+            along_track,
+        })
+    }
+}
+
+/// Where the footprint of each record comes from, in the columns following the
+/// longitude, latitude and height.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FootprintColumns {
+    /// The records are points.
+    #[default]
+    None,
+    /// The along-scan and along-track sizes of the pixel, in km.
+    Size,
+    /// The sensor zenith angle in degrees, from which the footprint is derived
+    /// using the nadir resolution and altitude of the sensor (in km).
+    Zenith {
+        nadir_resolution: f64,
+        altitude: f64,
+    },
+}
+
+impl FootprintColumns {
+    /// The footprint of a csv record, from its extra columns.
+    pub fn footprint(&self, record: &StringRecord) -> Result<Option<Footprint>, Box<dyn Error>> {
+        let column = |index: usize| -> Result<f64, Box<dyn Error>> {
+            let value = record.get(index).ok_or(format!(
+                "The record {:?} has no footprint column {}",
+                record.iter().collect::<Vec<&str>>().join(","),
+                index + 1
+            ))?;
+            Ok(value.trim().parse()?)
+        };
+
+        let footprint = match self {
+            FootprintColumns::None => None,
+            FootprintColumns::Size => Some(Footprint {
+                along_scan: column(3)?,
+                along_track: column(4)?,
+            }),
+            FootprintColumns::Zenith {
+                nadir_resolution,
+                altitude,
+            } => Some(Footprint::from_zenith(
+                column(3)?,
+                *nadir_resolution,
+                *altitude,
+            )?),
+        };
+        Ok(footprint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv::StringRecord;
+
+    use crate::inputs::footprint::{Footprint, FootprintColumns};
+
+    #[test]
+    /// The footprint is the nadir resolution at nadir, and grows towards the swath edge.
+    fn footprint_from_zenith() {
+        let nadir = Footprint::from_zenith(0.0, 0.75, 829.0).unwrap();
+        assert!((nadir.along_scan - 0.75).abs() < 1.0e-9);
+        assert!((nadir.along_track - 0.75).abs() < 1.0e-9);
+
+        let edge = Footprint::from_zenith(70.0, 0.75, 829.0).unwrap();
+        assert!(edge.along_track > 1.5 && edge.along_track < edge.along_scan);
+    }
+
+    #[test]
+    /// Zenith angles at or beyond the horizon, or negative, are rejected.
+    fn zenith_outside_range() {
+        for zenith in [-1.0, 90.0, 135.0, f64::NAN] {
+            assert!(Footprint::from_zenith(zenith, 0.75, 829.0).is_err());
+        }
+
+        let zenith = FootprintColumns::Zenith {
+            nadir_resolution: 0.75,
+            altitude: 829.0,
+        };
+        let record = StringRecord::from(vec!["100.0", "35.2", "12345", "95.0"]);
+        assert!(zenith.footprint(&record).is_err());
+    }
+
+    #[test]
+    /// The footprint is read from the columns after the height.
+    fn footprint_columns() {
+        let record = StringRecord::from(vec!["100.0", "35.2", "12345", "1.5", "0.8"]);
+        assert_eq!(
+            FootprintColumns::Size.footprint(&record).unwrap(),
+            Some(Footprint {
+                along_scan: 1.5,
+                along_track: 0.8
+            })
+        );
+        assert_eq!(FootprintColumns::None.footprint(&record).unwrap(), None);
+
+        let record = StringRecord::from(vec!["100.0", "35.2", "12345"]);
+        assert!(FootprintColumns::Size.footprint(&record).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::inputs::compression::is_stdio;
+
+/// The default patterns used to find the granule time in a filename, covering
+/// the Himawari ("_20230901_0000_"), VIIRS ("_d20230901_t0000000_") and GOES
+/// ("_s20232440000000_") naming conventions.
+pub const DEFAULT_TIME_PATTERNS: [&str; 3] = ["d%Y%m%d_t%H%M%S", "s%Y%j%H%M%S", "%Y%m%d_%H%M"];
+
+/// The formats accepted for the --start and --end options.
+const WINDOW_FORMATS: [&str; 4] = [
+    "%Y-%m-%dT%H:%M:%SZ",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%MZ",
+    "%Y-%m-%dT%H:%M",
+];
+
+/// Reads a list of input paths, one per line, from a file or from stdin if the
+/// filename is "-". Blank lines and lines starting with '#' are skipped.
+pub fn read_file_list(list_file: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    log::info!("Reading the input file list {list_file:?}...");
+
+    let reader: Box<dyn BufRead> = if is_stdio(list_file) {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(list_file)?))
+    };
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        paths.push(PathBuf::from(line));
+    }
+
+    log::info!("There are {} paths in the file list.", paths.len());
+
+    Ok(paths)
+}
+
+/// Parses a --start or --end time, e.g. "2023-09-01T06:00:00Z" or "2023-09-01".
+pub fn parse_window_time(time: &str) -> Result<NaiveDateTime, Box<dyn Error>> {
+    for format in WINDOW_FORMATS {
+        if let Ok(dt) = NaiveDateTime::parse_from_str(time, format) {
+            return Ok(dt);
+        }
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(time, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).unwrap());
+    }
+    Err(format!(
+        "Could not parse the time {time:?}, expected e.g. \"2023-09-01T06:00:00Z\" or \"2023-09-01\""
+    ))?
+}
+
+/// Finds the granule time in a filename by matching the pattern (a strftime-style
+/// format) at every position in the name. Patterns without a time of day give
+/// the start of the day.
+pub fn granule_time(file_name: &str, pattern: &str) -> Option<NaiveDateTime> {
+    for (idx, _) in file_name.char_indices() {
+        let text = &file_name[idx..];
+        if let Ok((dt, _)) = NaiveDateTime::parse_and_remainder(text, pattern) {
+            return Some(dt);
+        }
+        if let Ok((date, _)) = NaiveDate::parse_and_remainder(text, pattern) {
+            return date.and_hms_opt(0, 0, 0);
+        }
+    }
+    None
+}
+
+/// The TimeWindow struct holds the optional start and end of the granule
+/// times to select. The window includes its start but not its end, so
+/// consecutive windows don't share granules.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TimeWindow {
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+    pub patterns: Vec<String>,
+    /// Skip the files whose granule time can't be found, rather than stopping.
+    pub skip_untimed: bool,
+}
+
+impl TimeWindow {
+    pub fn is_set(&self) -> bool {
+        self.start.is_some() || self.end.is_some()
+    }
+
+    pub fn contains(&self, time: &NaiveDateTime) -> bool {
+        self.start.is_none_or(|start| *time >= start) && self.end.is_none_or(|end| *time < end)
+    }
+
+    /// The granule time of a file, using the first pattern which matches its name.
+    pub fn file_time(&self, file_path: &Path) -> Option<NaiveDateTime> {
+        let file_name = file_path.file_name()?.to_string_lossy();
+        self.patterns
+            .iter()
+            .find_map(|pattern| granule_time(&file_name, pattern))
+    }
+
+    /// Selects the files whose granule time is inside the window. Stdin has no
+    /// name to find the time in, so it's always kept. The other files whose time
+    /// can't be found are an error, unless they're skipped with --skip-untimed.
+    pub fn select(&self, files: Vec<PathBuf>) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        if !self.is_set() {
+            return Ok(files);
+        }
+
+        let num_files = files.len();
+        let mut selected: Vec<PathBuf> = Vec::with_capacity(num_files);
+        for file_path in files {
+            match self.file_time(&file_path) {
+                _ if is_stdio(&file_path) => selected.push(file_path),
+                Some(time) => {
+                    log::debug!("\tThe granule time of {file_path:?} is {time}");
+                    if self.contains(&time) {
+                        selected.push(file_path);
+                    }
+                }
+                None if self.skip_untimed => {
+                    log::warn!("Could not find the granule time of {file_path:?}, skipping it.");
+                }
+                None => Err(format!(
+                    "Could not find the granule time of {file_path:?} for --start/--end, \
+                     use --time-pattern to match its name or --skip-untimed to skip it."
+                ))?,
+            }
+        }
+
+        log::info!(
+            "Selected {} of {num_files} files between {:?} and {:?}.",
+            selected.len(),
+            self.start,
+            self.end
+        );
+
+        Ok(selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::NaiveDate;
+
+    use crate::inputs::granules::{
+        granule_time, parse_window_time, TimeWindow, DEFAULT_TIME_PATTERNS,
+    };
+
+    fn time(h: u32, m: u32, s: u32) -> chrono::NaiveDateTime {
+        NaiveDate::from_ymd_opt(2023, 9, 1)
+            .unwrap()
+            .and_hms_opt(h, m, s)
+            .unwrap()
+    }
+
+    #[test]
+    /// The default patterns find the time in the usual granule names.
+    fn granule_time_from_filenames() {
+        let window = TimeWindow {
+            patterns: DEFAULT_TIME_PATTERNS.map(String::from).to_vec(),
+            ..TimeWindow::default()
+        };
+        let cases = [
+            ("HS_H08_20230901_0310_B13_FLDK.csv", time(3, 10, 0)),
+            ("cth_j01_d20230901_t0512345_e0513567.csv", time(5, 12, 34)),
+            (
+                "OR_ABI-L2-ACHAF-M6_G16_s20232440100205_e.csv",
+                time(1, 0, 20),
+            ),
+        ];
+        for (file_name, expected) in cases {
+            assert_eq!(
+                window.file_time(&PathBuf::from(file_name)),
+                Some(expected),
+                "{file_name}"
+            );
+        }
+        assert_eq!(
+            window.file_time(&PathBuf::from("viirs_cloud_top_height.csv")),
+            None
+        );
+    }
+
+    #[test]
+    /// A date-only pattern gives the start of the day.
+    fn granule_time_date_only() {
+        assert_eq!(
+            granule_time("cth.2023-09-01.csv", "%Y-%m-%d"),
+            Some(time(0, 0, 0))
+        );
+    }
+
+    #[test]
+    /// The window includes its start but not its end.
+    fn time_window_selection() {
+        let window = TimeWindow {
+            start: Some(parse_window_time("2023-09-01T00:00Z").unwrap()),
+            end: Some(parse_window_time("2023-09-01T06:00:00Z").unwrap()),
+            patterns: vec!["%Y%m%d_%H%M".to_string()],
+            skip_untimed: false,
+        };
+        let files: Vec<PathBuf> = [
+            "a_20230831_2350.csv",
+            "a_20230901_0000.csv",
+            "-",
+            "a_20230901_0550.csv",
+            "a_20230901_0600.csv",
+            "a.csv",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        // Files without a granule time are an error, unless they're skipped
+        assert!(window.select(files.clone()).is_err());
+        let window = TimeWindow {
+            skip_untimed: true,
+            ..window
+        };
+        assert_eq!(
+            window.select(files).unwrap(),
+            [
+                PathBuf::from("a_20230901_0000.csv"),
+                PathBuf::from("-"),
+                PathBuf::from("a_20230901_0550.csv")
+            ]
+        );
+    }
+
+    #[test]
+    /// Badly formatted window times are an error.
+    fn parse_window_time_formats() {
+        assert_eq!(parse_window_time("2023-09-01").unwrap(), time(0, 0, 0));
+        assert_eq!(
+            parse_window_time("2023-09-01T03:04:05").unwrap(),
+            time(3, 4, 5)
+        );
+        assert!(parse_window_time("06Z").is_err());
+    }
+}
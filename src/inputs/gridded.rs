@@ -0,0 +1,307 @@
+use std::{error::Error, io::Read, path::Path};
+
+use serde_json::Value;
+
+use crate::compute::{gcd, micro_degrees, MICRO_DEGREES};
+use crate::inputs::compression::{uncompressed_extension, Input};
+use crate::inputs::sensors::GridExtent;
+use crate::outputs::report::GridSpec;
+use crate::outputs::writes::COLUMNS;
+use crate::outputs::WriteRecord;
+
+/// The GriddedOutput struct holds the cells of a grid written by an earlier run,
+/// and the metadata written with them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GriddedOutput {
+    pub metadata: Vec<(String, String)>,
+    pub records: Vec<WriteRecord>,
+}
+
+/// Checks the columns are those written by default, which are the columns of
+/// COLUMNS in order, with or without the cell area.
+fn check_columns(names: &[&str]) -> Result<(), Box<dyn Error>> {
+    if names != &COLUMNS[..7] && names != COLUMNS {
+        Err(format!(
+            "The columns {} aren't those of a gridded output, {} (optionally followed by {})",
+            names.join(","),
+            COLUMNS[..7].join(","),
+            COLUMNS[7]
+        ))?
+    }
+    Ok(())
+}
+
+impl GriddedOutput {
+    /// Reads the cells of a csv or GeoJSON output, which may be compressed.
+    pub fn read(path: &Path) -> Result<GriddedOutput, Box<dyn Error>> {
+        log::info!("Reading the gridded output {path:?}...");
+        let mut text = String::new();
+        Input::open(path)?.read_to_string(&mut text)?;
+        let output = match uncompressed_extension(path).as_deref() {
+            Some("geojson" | "json") => GriddedOutput::from_geojson(&text),
+            Some("tif" | "tiff" | "png") => {
+                Err("Only csv and GeoJSON gridded outputs can be read".into())
+            }
+            _ => GriddedOutput::from_csv(&text),
+        }
+        .map_err(|e| format!("{path:?}: {e}"))?;
+        log::info!(
+            "\tThere are {} cells in the gridded output.",
+            output.records.len()
+        );
+        Ok(output)
+    }
+
+    /// Reads the cells of a csv output, after the metadata comment lines.
+    pub fn from_csv(text: &str) -> Result<GriddedOutput, Box<dyn Error>> {
+        let metadata: Vec<(String, String)> = text
+            .lines()
+            .map_while(|line| line.strip_prefix('#'))
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+            .collect();
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(text.as_bytes());
+        check_columns(&rdr.headers()?.iter().collect::<Vec<&str>>())?;
+
+        let records = rdr
+            .deserialize()
+            .collect::<Result<Vec<WriteRecord>, csv::Error>>()?;
+        Ok(GriddedOutput::new(metadata, records))
+    }
+
+    /// Reads the cells of a GeoJSON output from the properties of its features.
+    pub fn from_geojson(text: &str) -> Result<GriddedOutput, Box<dyn Error>> {
+        let geojson: Value = serde_json::from_str(text)?;
+        let metadata: Vec<(String, String)> = match &geojson["metadata"] {
+            Value::Object(metadata) => metadata
+                .iter()
+                .map(|(key, value)| match value {
+                    Value::String(value) => (key.clone(), value.clone()),
+                    _ => (key.clone(), value.to_string()),
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        let features = geojson["features"]
+            .as_array()
+            .ok_or("The GeoJSON isn't a feature collection")?;
+
+        let mut records: Vec<WriteRecord> = Vec::with_capacity(features.len());
+        for feature in features {
+            let properties = feature["properties"]
+                .as_object()
+                .ok_or("A feature has no properties")?;
+            check_columns(&properties.keys().map(String::as_str).collect::<Vec<&str>>())?;
+            records.push(serde_json::from_value(Value::Object(properties.clone()))?);
+        }
+        Ok(GriddedOutput::new(metadata, records))
+    }
+
+    /// The gridded output of the cells. The statistics of empty cells, which may
+    /// have been written with a fill value, are taken to be missing.
+    fn new(metadata: Vec<(String, String)>, mut records: Vec<WriteRecord>) -> GriddedOutput {
+        for record in records.iter_mut().filter(|record| record.counts == 0) {
+            record.mean_height = f64::NAN;
+            record.stdev_height = f64::NAN;
+        }
+        GriddedOutput { metadata, records }
+    }
+
+    /// The value of a metadata item.
+    pub fn metadata_value(&self, key: &str) -> Option<&str> {
+        self.metadata
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The grid of the output, from its metadata, or from the spacing of the
+    /// cell centers for an output on the lon/lat grid without any metadata.
+    pub fn grid_spec(&self) -> Result<GridSpec, Box<dyn Error>> {
+        let parse = |key: &str| -> Result<Option<f64>, Box<dyn Error>> {
+            self.metadata_value(key)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| format!("Invalid {key} {value:?} in the metadata"))
+                })
+                .transpose()
+                .map_err(|e| e.into())
+        };
+        let grid_size = match parse("grid_size")? {
+            Some(grid_size) => grid_size,
+            None => self.infer_grid_size()?,
+        };
+        Ok(GridSpec {
+            grid: self
+                .metadata_value("grid")
+                .map_or(format!("lon/lat {grid_size} degree"), String::from),
+            grid_size,
+            sensor: self
+                .metadata_value("sensor")
+                .filter(|sensor| *sensor != "unknown")
+                .map(String::from),
+            native_resolution_km: parse("native_resolution_km")?,
+            grid_extent: self.metadata_value("grid_extent").map(String::from),
+        })
+    }
+
+    /// The extent of the grid, from its metadata, or the whole globe for an output
+    /// without one.
+    pub fn grid_extent(&self) -> Result<GridExtent, Box<dyn Error>> {
+        let Some(value) = self.metadata_value("grid_extent") else {
+            return Ok(GridExtent::GLOBAL);
+        };
+        let bounds = value
+            .split(',')
+            .map(|bound| bound.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>();
+        match bounds.as_deref() {
+            Ok([west, east, south, north]) if west < east && south < north => Ok(GridExtent {
+                west: *west,
+                east: *east,
+                south: *south,
+                north: *north,
+            }),
+            _ => Err(format!("Invalid grid_extent {value:?} in the metadata"))?,
+        }
+    }
+
+    /// The size of the cells of an output on the lon/lat grid, which is needed
+    /// to merge its cells or regrid them.
+    pub fn lon_lat_grid_size(&self) -> Result<f64, Box<dyn Error>> {
+        let spec = self.grid_spec()?;
+        if !spec.grid.starts_with("lon/lat") {
+            Err(format!(
+                "Only gridded outputs on the lon/lat grid can be merged or regridded, not the {} grid.",
+                spec.grid
+            ))?
+        }
+        Ok(spec.grid_size)
+    }
+
+    /// The size of the lon/lat grid whose cells are centered on the cell centers,
+    /// the largest whose cell edges lie between all of them.
+    fn infer_grid_size(&self) -> Result<f64, Box<dyn Error>> {
+        let mut longitudes: Vec<i64> = self
+            .records
+            .iter()
+            .map(|r| micro_degrees(r.longitude))
+            .collect();
+        let mut latitudes: Vec<i64> = self
+            .records
+            .iter()
+            .map(|r| micro_degrees(r.latitude))
+            .collect();
+        longitudes.sort();
+        latitudes.sort();
+
+        // The centers are an odd number of half cells from the origin
+        let mut step: i64 = 0;
+        for values in [&longitudes, &latitudes] {
+            for pair in values.windows(2) {
+                step = gcd(pair[1] - pair[0], step);
+            }
+            for value in values.iter() {
+                step = gcd(2 * value, step);
+            }
+        }
+        if step == 0 || longitudes.len() < 2 {
+            Err("The grid size can't be found from the cells, and isn't in the metadata")?
+        }
+        if self.records.iter().any(|r| {
+            (2 * micro_degrees(r.longitude) / step) % 2 == 0
+                || (2 * micro_degrees(r.latitude) / step) % 2 == 0
+        }) {
+            Err("The cells aren't centered on the cells of a lon/lat grid")?
+        }
+        let grid_size = step as f64 / MICRO_DEGREES;
+        log::info!("The cells are on the {grid_size} degree lon/lat grid");
+        Ok(grid_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inputs::gridded::GriddedOutput;
+    use crate::inputs::sensors::GridExtent;
+
+    #[test]
+    /// The cells and metadata are read back from csv and GeoJSON outputs.
+    fn read_gridded_outputs() {
+        let csv = "# grid: lon/lat 0.5 degree\n# grid_size: 0.5\n# sensor: unknown\n\
+                   Longitude,Latitude,Counts,SumHeights,SumSquaredHeights,MeanHeight,StdevHeight\n\
+                   10.25,-0.25,2,3000,5000000,1500,500\n\
+                   10.75,-0.25,0,0,0,-9999,\n";
+        let output = GriddedOutput::from_csv(csv).unwrap();
+        assert_eq!(output.records.len(), 2);
+        assert_eq!(output.records[0].counts, 2);
+        assert_eq!(output.records[0].mean_height, 1500.0);
+        assert!(output.records[0].cell_area.is_nan());
+        assert!(output.records[1].mean_height.is_nan());
+        let spec = output.grid_spec().unwrap();
+        assert_eq!(
+            (spec.grid.as_str(), spec.grid_size),
+            ("lon/lat 0.5 degree", 0.5)
+        );
+        assert_eq!(spec.sensor, None);
+
+        let geojson = r#"{"type": "FeatureCollection", "metadata": {"grid_size": "0.5"},
+            "features": [{"type": "Feature", "geometry": null, "properties":
+            {"Longitude": 10.25, "Latitude": -0.25, "Counts": 2, "SumHeights": 3000,
+             "SumSquaredHeights": 5000000, "MeanHeight": 1500.0, "StdevHeight": null}}]}"#;
+        let output = GriddedOutput::from_geojson(geojson).unwrap();
+        assert_eq!(output.records[0].sum_heights, 3000);
+        assert!(output.records[0].stdev_height.is_nan());
+        assert_eq!(output.lon_lat_grid_size().unwrap(), 0.5);
+
+        // Renamed or missing columns can't be read back
+        assert!(GriddedOutput::from_csv("Longitude,Latitude,Counts\n1,2,3\n").is_err());
+    }
+
+    #[test]
+    /// Without any metadata, the grid size is found from the cell centers.
+    fn inferred_grid_size() {
+        let header =
+            "Longitude,Latitude,Counts,SumHeights,SumSquaredHeights,MeanHeight,StdevHeight\n";
+        let output = GriddedOutput::from_csv(&format!(
+            "{header}10.125,-0.625,1,1,1,1,0\n10.875,-0.125,1,1,1,1,0\n"
+        ))
+        .unwrap();
+        assert_eq!(output.grid_spec().unwrap().grid_size, 0.25);
+
+        let output =
+            GriddedOutput::from_csv(&format!("{header}10.5,0.5,1,1,1,1,0\n12.5,2.5,1,1,1,1,0\n"))
+                .unwrap();
+        assert_eq!(output.lon_lat_grid_size().unwrap(), 1.0);
+    }
+
+    #[test]
+    /// The extent of the grid is read from the metadata, and is global without it.
+    fn grid_extents() {
+        let header = "Longitude,Latitude,Counts,SumHeights,SumSquaredHeights,MeanHeight,StdevHeight
+";
+        let output = GriddedOutput::from_csv(header).unwrap();
+        assert_eq!(output.grid_extent().unwrap(), GridExtent::GLOBAL);
+
+        let output =
+            GriddedOutput::from_csv(&format!("# grid_extent: 59.7,221.7,-81,81\n{header}"))
+                .unwrap();
+        assert_eq!(
+            output.grid_extent().unwrap(),
+            GridExtent {
+                west: 59.7,
+                east: 221.7,
+                south: -81.0,
+                north: 81.0
+            }
+        );
+
+        let output = GriddedOutput::from_csv(&format!("# grid_extent: 0,10\n{header}")).unwrap();
+        assert!(output.grid_extent().is_err());
+    }
+}
@@ -1,9 +1,16 @@
-use std::{error::Error, path::PathBuf};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
 // use csv::DeserializeError;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-// use crate::{MAX_RECORDS, GRID_SIZE};
-use crate::MAX_RECORDS;
+use crate::inputs::compression::Input;
+use crate::inputs::footprint::{Footprint, FootprintColumns};
+use crate::progress::Progress;
+
+/// The number of records between progress updates.
+const PROGRESS_INTERVAL: usize = 10_000;
 
 /// The Record struct holds a single line of data read from a csv file
 #[derive(Debug, Deserialize, Clone, PartialEq)]
@@ -12,87 +19,268 @@ pub struct ReadRecord {
     pub longitude: f64,
     pub latitude: f64,
     pub height: i64,
+    /// The ground footprint of the pixel, if read from the extra columns.
+    #[serde(skip)]
+    pub footprint: Option<Footprint>,
+}
+
+/// The ReadLimits struct holds the limits on the number of records read, and the
+/// number of records to hold in memory before they are handed on for binning. It
+/// also says which extra columns hold the footprint of each record.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReadLimits {
+    /// Maximum number of records read from each file.
+    pub per_file: Option<usize>,
+    /// Maximum number of records read from all of the files.
+    pub total: Option<usize>,
+    /// Number of records to buffer before passing them on. If unset, all of the
+    /// records are passed on together once every file has been read.
+    pub chunk_size: Option<usize>,
+    pub footprint: FootprintColumns,
+}
+
+/// The FileReadSummary struct holds the number of records read from a file.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileReadSummary {
+    pub path: PathBuf,
+    pub num_records: usize,
+    /// Whether reading stopped early because of the record limits.
+    pub truncated: bool,
+}
+
+/// The handler that the readers pass the records to, along with the file they came from.
+pub type ChunkHandler<'a> = dyn FnMut(&Path, Vec<ReadRecord>) -> Result<(), Box<dyn Error>> + 'a;
+
+/// The RecordBuffer struct collects the records from the readers, enforcing the
+/// read limits and passing the records on in chunks. A chunk never spans files.
+struct RecordBuffer<'a, 'b> {
+    limits: &'a ReadLimits,
+    records: Vec<ReadRecord>,
+    num_total: usize,
+    num_chunks: usize,
+    summaries: Vec<FileReadSummary>,
+    on_chunk: &'a mut ChunkHandler<'b>,
+}
+
+impl<'a, 'b> RecordBuffer<'a, 'b> {
+    fn new(limits: &'a ReadLimits, on_chunk: &'a mut ChunkHandler<'b>) -> Self {
+        RecordBuffer {
+            limits,
+            records: Vec::new(),
+            num_total: 0,
+            num_chunks: 0,
+            summaries: Vec::new(),
+            on_chunk,
+        }
+    }
+
+    /// Whether the total record limit has been reached.
+    fn is_full(&self) -> bool {
+        self.limits.total.is_some_and(|max| self.num_total >= max)
+    }
+
+    /// Passes on the records of the previous file, and starts counting those of the next.
+    fn start_file(&mut self, file_path: &Path) -> Result<(), Box<dyn Error>> {
+        self.flush()?;
+        self.summaries.push(FileReadSummary {
+            path: file_path.to_path_buf(),
+            num_records: 0,
+            truncated: false,
+        });
+        Ok(())
+    }
+
+    /// Checks the limits ahead of reading another record from the current file. Returns
+    /// false (after warning about the truncation) if no more records should be read.
+    fn accepts(&mut self) -> bool {
+        let is_full = self.is_full();
+        let per_file = self.limits.per_file;
+        let num_total = self.num_total;
+        let Some(summary) = self.summaries.last_mut() else {
+            return false;
+        };
+        let (file_path, num_records) = (&summary.path, summary.num_records);
+
+        if per_file.is_some_and(|max| num_records >= max) {
+            log::warn!(
+                "Truncated the file {file_path:?} after {num_records} records (--max-records)."
+            );
+        } else if is_full {
+            log::warn!(
+                "Truncated the file {file_path:?} after {num_records} records, as the total of {num_total} records has been reached (--max-records-total)."
+            );
+        } else {
+            return true;
+        }
+        summary.truncated = true;
+        false
+    }
+
+    fn push(&mut self, record: ReadRecord) -> Result<(), Box<dyn Error>> {
+        self.records.push(record);
+        self.num_total += 1;
+        if let Some(summary) = self.summaries.last_mut() {
+            summary.num_records += 1;
+        }
+
+        if let Some(size) = self
+            .limits
+            .chunk_size
+            .filter(|size| self.records.len() >= *size)
+        {
+            if self.num_chunks == 0 {
+                log::info!(
+                    "Reached the in-memory limit of {size} records, passing them on in chunks."
+                );
+            }
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.records.is_empty() {
+            return Ok(());
+        }
+        let Some(summary) = self.summaries.last() else {
+            return Ok(());
+        };
+        self.num_chunks += 1;
+        (self.on_chunk)(&summary.path, std::mem::take(&mut self.records))
+    }
+
+    /// Passes on any remaining records, and returns the summaries of the files read.
+    fn finish(mut self, num_files: usize) -> Result<Vec<FileReadSummary>, Box<dyn Error>> {
+        self.flush()?;
+        let num_files_read = self.summaries.len();
+        if num_files_read < num_files {
+            log::warn!(
+                "Skipped {} of {num_files} files, as the total of {} records has been reached (--max-records-total).",
+                num_files - num_files_read,
+                self.num_total
+            );
+        }
+        Ok(self.summaries)
+    }
 }
 
 /// Reads in a CSV file, using the csv crate and deserializing with serde crate.
-/// Returns Ok(Vec<ReadRecord>).
+/// The records are passed to `on_chunk` as they are read, in chunks of
+/// `limits.chunk_size` records. Returns Ok(Vec<FileReadSummary>).
 pub fn read_using_csv_serde(
     // files: &Vec<&String>,
     files: &Vec<&PathBuf>,
-    max_records: &usize,
-) -> Result<Vec<ReadRecord>, Box<dyn Error>> {
-    // let mut csv_records: Vec<ReadRecord> = Vec::new();
-    let mut csv_records: Vec<ReadRecord> = Vec::with_capacity(MAX_RECORDS);
+    limits: &ReadLimits,
+    on_chunk: &mut ChunkHandler,
+    progress: &Progress,
+) -> Result<Vec<FileReadSummary>, Box<dyn Error>> {
+    let mut buffer = RecordBuffer::new(limits, on_chunk);
+    let mut bytes_done: u64 = 0;
 
     for file_path in files {
-        log::info!("Reading the file '{file_path:?}' using csv crate with serde deserialization...");
-
-        let mut num_records: i64 = 0;
-
+        if buffer.is_full() {
+            break;
+        }
+        log::info!(
+            "Reading the file '{file_path:?}' using csv crate with serde deserialization..."
+        );
         // Dereference
         let file_path = *file_path;
 
+        buffer.start_file(file_path)?;
+        let mut num_records: usize = 0;
+
+        // Compressed files (and stdin) are decompressed on the fly
+        let input = Input::open(file_path)?;
+        let bytes_read = input.bytes_read();
+        let file_size = file_path.metadata().map_or(0, |m| m.len());
+
         let mut rdr = csv::ReaderBuilder::new()
             .has_headers(false)
-            .from_path(file_path)?;
+            .comment(Some(b'#'))
+            .from_reader(input);
 
-        for result in rdr.deserialize() {
-            let record: ReadRecord = result?;
-
-            if num_records as usize > *max_records {
-                log::info!("Breaking at {}", num_records);
+        for result in rdr.records() {
+            if !buffer.accepts() {
                 break;
             }
+            let row = result?;
+            let mut record: ReadRecord = row.deserialize(None)?;
+            record.footprint = limits.footprint.footprint(&row)?;
             num_records += 1;
+            if num_records.is_multiple_of(PROGRESS_INTERVAL) {
+                progress.update(bytes_done + bytes_read.get(), PROGRESS_INTERVAL as u64);
+            }
 
-            csv_records.push(record);
+            buffer.push(record)?;
         }
 
+        bytes_done += file_size.max(bytes_read.get());
+        progress.update(bytes_done, (num_records % PROGRESS_INTERVAL) as u64);
+        progress.file_done();
+
         log::info!("\tFinished deserializing the csv file...");
         log::info!("\tThere are {:?} entries in the csv file.\n", num_records);
     }
 
+    let summaries = buffer.finish(files.len())?;
+    let num_total: usize = summaries.iter().map(|s| s.num_records).sum();
+
     log::info!("Finished reading the csv files...");
-    log::info!("There are {:?} total entries read.\n", csv_records.len());
+    log::info!("There are {:?} total entries read.\n", num_total);
 
     // Err("This is an error")?
-    Ok(csv_records)
+    Ok(summaries)
 }
 
 /// Reads in a CSV file, using the csv crate and manually deserializing.
-/// Returns Ok(Vec<ReadRecord>).
+/// The records are passed to `on_chunk` as they are read, in chunks of
+/// `limits.chunk_size` records. Returns Ok(Vec<FileReadSummary>).
 pub fn read_using_csv(
     // files: &Vec<&String>,
     files: &Vec<&PathBuf>,
-    max_records: &usize,
-) -> Result<Vec<ReadRecord>, Box<dyn Error>> {
-    // let mut csv_records: Vec<ReadRecord> = Vec::new();
-    let mut csv_records: Vec<ReadRecord> = Vec::with_capacity(MAX_RECORDS);
+    limits: &ReadLimits,
+    on_chunk: &mut ChunkHandler,
+    progress: &Progress,
+) -> Result<Vec<FileReadSummary>, Box<dyn Error>> {
+    let mut buffer = RecordBuffer::new(limits, on_chunk);
+    let mut bytes_done: u64 = 0;
 
     for file_path in files {
+        if buffer.is_full() {
+            break;
+        }
         log::info!("Reading the file '{file_path:?}' using csv crate with manual destructuring...");
-
-        let mut num_records: i64 = 0;
-
         // Dereference
         let file_path = *file_path;
 
+        buffer.start_file(file_path)?;
+        let mut num_records: usize = 0;
+
+        // Compressed files (and stdin) are decompressed on the fly
+        let input = Input::open(file_path)?;
+        let bytes_read = input.bytes_read();
+        let file_size = file_path.metadata().map_or(0, |m| m.len());
+
         let mut rdr = csv::ReaderBuilder::new()
             .has_headers(false)
-            .from_path(file_path)?;
+            .comment(Some(b'#'))
+            .from_reader(input);
 
         for result in rdr.records() {
-            let record = result?;
-
-            if num_records as usize > *max_records {
-                log::info!("Breaking at {}", num_records);
+            if !buffer.accepts() {
                 break;
             }
+            let record = result?;
             num_records += 1;
+            if num_records.is_multiple_of(PROGRESS_INTERVAL) {
+                progress.update(bytes_done + bytes_read.get(), PROGRESS_INTERVAL as u64);
+            }
 
             let longitude: f64 = record[0].parse()?;
             let latitude: f64 = record[1].parse()?;
             let height: i64 = record[2].parse()?;
+            let footprint = limits.footprint.footprint(&record)?;
 
             // When the struct field names are the same as the variables they are being populated with,
             // we can replace '"fieldname": varname' with just "varname".
@@ -100,24 +288,133 @@ pub fn read_using_csv(
                 longitude,
                 latitude,
                 height,
+                footprint,
             };
-            csv_records.push(record);
+            buffer.push(record)?;
         }
 
+        bytes_done += file_size.max(bytes_read.get());
+        progress.update(bytes_done, (num_records % PROGRESS_INTERVAL) as u64);
+        progress.file_done();
+
         log::info!("Finished looping through the lines...");
         log::info!("There are {:?} observations", num_records);
     }
 
+    let summaries = buffer.finish(files.len())?;
+    let num_total: usize = summaries.iter().map(|s| s.num_records).sum();
+
     log::info!("Finished reading the csv files...");
-    log::info!("There are {:?} total entries read.\n", csv_records.len());
+    log::info!("There are {:?} total entries read.\n", num_total);
 
-    Ok(csv_records)
+    Ok(summaries)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::inputs::reads::{read_using_csv, read_using_csv_serde, ReadLimits};
+    use crate::progress::Progress;
     use crate::ReadRecord;
 
+    /// Writes a csv file of `num_records` records for a test.
+    fn make_csv(name: &str, num_records: usize) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("grid_test_{name}_{}.csv", std::process::id()));
+        let lines: Vec<String> = (0..num_records)
+            .map(|i| format!("100.0,35.2,{i}\n"))
+            .collect();
+        fs::write(&path, lines.concat()).unwrap();
+        path
+    }
+
+    #[test]
+    /// The per-file and total limits are exact, and the chunks add up to the records read.
+    fn read_limits() {
+        let file_1 = make_csv("limits_1", 10);
+        let file_2 = make_csv("limits_2", 10);
+        let files = vec![&file_1, &file_2];
+
+        let cases = [
+            (ReadLimits::default(), 20),
+            (
+                ReadLimits {
+                    per_file: Some(4),
+                    ..ReadLimits::default()
+                },
+                8,
+            ),
+            (
+                ReadLimits {
+                    total: Some(13),
+                    ..ReadLimits::default()
+                },
+                13,
+            ),
+            (
+                ReadLimits {
+                    total: Some(5),
+                    ..ReadLimits::default()
+                },
+                5,
+            ),
+        ];
+        for (limits, expected) in cases {
+            for reader in [read_using_csv, read_using_csv_serde] {
+                let mut records: Vec<ReadRecord> = Vec::new();
+                let summaries = reader(
+                    &files,
+                    &limits,
+                    &mut |_, chunk| {
+                        records.extend(chunk);
+                        Ok(())
+                    },
+                    &Progress::hidden(),
+                )
+                .unwrap();
+                let num_read: usize = summaries.iter().map(|s| s.num_records).sum();
+                assert_eq!(num_read, expected, "{limits:?}");
+                assert_eq!(records.len(), expected, "{limits:?}");
+                assert_eq!(
+                    summaries.iter().any(|s| s.truncated),
+                    expected < 20,
+                    "{limits:?}"
+                );
+            }
+        }
+
+        fs::remove_file(file_1).unwrap();
+        fs::remove_file(file_2).unwrap();
+    }
+
+    #[test]
+    /// The records are passed on in chunks of the requested size.
+    fn read_in_chunks() {
+        let file = make_csv("chunks", 10);
+        let limits = ReadLimits {
+            chunk_size: Some(4),
+            ..ReadLimits::default()
+        };
+
+        let mut chunk_sizes: Vec<usize> = Vec::new();
+        read_using_csv_serde(
+            &vec![&file, &file],
+            &limits,
+            &mut |path, chunk| {
+                assert_eq!(path, file);
+                chunk_sizes.push(chunk.len());
+                Ok(())
+            },
+            &Progress::hidden(),
+        )
+        .unwrap();
+        // Chunks don't span files
+        assert_eq!(chunk_sizes, [4, 4, 2, 4, 4, 2]);
+
+        fs::remove_file(file).unwrap();
+    }
+
     #[test]
     /// This test checks that the struct attributes are the values
     /// they were defined as.
@@ -126,6 +423,7 @@ mod tests {
             longitude: 100.0,
             latitude: 35.2,
             height: 12345,
+            footprint: None,
         };
         assert_eq!(record.longitude, 100.0);
         assert_eq!(record.latitude, 35.2);
@@ -140,6 +438,7 @@ mod tests {
             longitude: 100.0,
             latitude: 35.2,
             height: 12345,
+            footprint: None,
         };
         let record_2 = record_1.clone();
         assert_eq!(record_1, record_2);
@@ -0,0 +1,487 @@
+use std::{collections::HashMap, error::Error, io::Read, path::Path};
+
+use serde_json::Value;
+
+use crate::inputs::compression::{uncompressed_extension, Input};
+
+/// The Polygon struct is an exterior ring with any holes, in degrees of
+/// longitude and latitude.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    rings: Vec<Vec<(f64, f64)>>,
+    /// The (west, east, south, north) bounds of the exterior ring.
+    bounds: (f64, f64, f64, f64),
+}
+
+impl Polygon {
+    pub fn new(rings: Vec<Vec<(f64, f64)>>) -> Result<Polygon, Box<dyn Error>> {
+        let exterior = rings.first().ok_or("A polygon has no rings")?;
+        if let Some(ring) = rings.iter().find(|ring| ring.len() < 3) {
+            Err(format!("A polygon ring has only {} points", ring.len()))?
+        }
+        let bounds = exterior.iter().fold(
+            (
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+            ),
+            |(west, east, south, north), (lon, lat)| {
+                (
+                    west.min(*lon),
+                    east.max(*lon),
+                    south.min(*lat),
+                    north.max(*lat),
+                )
+            },
+        );
+        Ok(Polygon { rings, bounds })
+    }
+
+    /// Whether the point is inside the polygon, by counting the crossings of a
+    /// ray to the east over all of the rings, so that points in holes are outside.
+    pub fn contains(&self, longitude: f64, latitude: f64) -> bool {
+        let (west, east, south, north) = self.bounds;
+        if longitude < west || longitude > east || latitude < south || latitude > north {
+            return false;
+        }
+
+        let mut inside = false;
+        for ring in &self.rings {
+            let mut previous = ring[ring.len() - 1];
+            for point in ring {
+                let ((x_0, y_0), (x_1, y_1)) = (previous, *point);
+                if (y_0 > latitude) != (y_1 > latitude)
+                    && longitude < x_0 + (latitude - y_0) * (x_1 - x_0) / (y_1 - y_0)
+                {
+                    inside = !inside;
+                }
+                previous = *point;
+            }
+        }
+        inside
+    }
+}
+
+/// The shapes of the regions.
+#[derive(Debug, Clone, PartialEq)]
+enum Shapes {
+    /// The polygons making up each region.
+    Polygons(Vec<Vec<Polygon>>),
+    /// A grid of cells of `grid_size` degrees, each holding the index of its region.
+    /// The cells are numbered from the corner `origin` of one of them.
+    Mask {
+        grid_size: f64,
+        origin: (f64, f64),
+        cells: HashMap<(i64, i64), usize>,
+    },
+}
+
+/// The (column, row) of the mask cell containing the point.
+fn mask_cell(longitude: f64, latitude: f64, grid_size: f64, origin: (f64, f64)) -> (i64, i64) {
+    (
+        ((longitude - origin.0) / grid_size).floor() as i64,
+        ((latitude - origin.1) / grid_size).floor() as i64,
+    )
+}
+
+/// The Regions struct holds the named regions the observations are selected from
+/// and summarized over. The regions may overlap, unless they come from a mask.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regions {
+    pub names: Vec<String>,
+    shapes: Shapes,
+}
+
+impl Regions {
+    /// Reads the regions from a GeoJSON file (.geojson or .json), or otherwise
+    /// from a file of WKT polygons, one region per line.
+    pub fn read(path: &Path) -> Result<Regions, Box<dyn Error>> {
+        log::info!("Reading the regions from {path:?}...");
+
+        let mut text = String::new();
+        Input::open(path)?.read_to_string(&mut text)?;
+
+        let regions = match uncompressed_extension(path).as_deref() {
+            Some("geojson" | "json") => Regions::from_geojson(&text),
+            _ => Regions::from_wkt(&text),
+        }
+        .map_err(|e| format!("Could not read the regions from {path:?}: {e}"))?;
+
+        log::info!(
+            "There are {} regions: {}",
+            regions.len(),
+            regions.names.join(", ")
+        );
+        Ok(regions)
+    }
+
+    /// The regions of the polygon and multipolygon features of a GeoJSON feature
+    /// collection, feature or geometry, named after their "name" property.
+    pub fn from_geojson(text: &str) -> Result<Regions, Box<dyn Error>> {
+        let geojson: Value = serde_json::from_str(text)?;
+        let features: Vec<&Value> = match geojson["type"].as_str() {
+            Some("FeatureCollection") => geojson["features"]
+                .as_array()
+                .ok_or("The feature collection has no features")?
+                .iter()
+                .collect(),
+            _ => vec![&geojson],
+        };
+
+        let mut names: Vec<String> = Vec::new();
+        let mut shapes: Vec<Vec<Polygon>> = Vec::new();
+        for feature in features {
+            let (geometry, properties) = match feature["type"].as_str() {
+                Some("Feature") => (&feature["geometry"], &feature["properties"]),
+                _ => (feature, &Value::Null),
+            };
+            let polygons: Vec<&Value> = match geometry["type"].as_str() {
+                Some("Polygon") => vec![&geometry["coordinates"]],
+                Some("MultiPolygon") => geometry["coordinates"]
+                    .as_array()
+                    .ok_or("A multipolygon has no coordinates")?
+                    .iter()
+                    .collect(),
+                other => {
+                    log::warn!("Skipping a feature with a geometry of {other:?}");
+                    continue;
+                }
+            };
+
+            let name = ["name", "NAME", "Name"]
+                .iter()
+                .find_map(|key| match &properties[key] {
+                    Value::String(name) => Some(name.clone()),
+                    Value::Number(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .unwrap_or(format!("region-{}", names.len() + 1));
+            names.push(name);
+            shapes.push(
+                polygons
+                    .into_iter()
+                    .map(geojson_polygon)
+                    .collect::<Result<_, _>>()?,
+            );
+        }
+        if names.is_empty() {
+            Err("There are no polygons")?
+        }
+
+        Ok(Regions {
+            names,
+            shapes: Shapes::Polygons(shapes),
+        })
+    }
+
+    /// The regions of the WKT polygons and multipolygons, one to a line, each
+    /// optionally preceded by the name of the region and a tab. Empty lines and
+    /// lines starting with '#' are skipped.
+    pub fn from_wkt(text: &str) -> Result<Regions, Box<dyn Error>> {
+        let mut names: Vec<String> = Vec::new();
+        let mut shapes: Vec<Vec<Polygon>> = Vec::new();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, wkt) = match line.split_once('\t') {
+                Some((name, wkt)) => (name.trim().to_string(), wkt),
+                None => (format!("region-{}", names.len() + 1), line),
+            };
+            names.push(name);
+            shapes.push(wkt_polygons(wkt)?);
+        }
+        if names.is_empty() {
+            Err("There are no polygons")?
+        }
+
+        Ok(Regions {
+            names,
+            shapes: Shapes::Polygons(shapes),
+        })
+    }
+
+    /// Reads a mask of regions on a lon/lat grid, from a csv file of the
+    /// Longitude and Latitude of the cell centers and the Region of each cell.
+    /// Cells with an empty region are in none of the regions. The size of the
+    /// cells is the smallest spacing of the cell centers, and the cell edges are
+    /// half a cell from the centers, which must all lie on the one grid.
+    pub fn read_mask(path: &Path) -> Result<Regions, Box<dyn Error>> {
+        log::info!("Reading the region mask {path:?}...");
+
+        let mut rdr = csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .trim(csv::Trim::All)
+            .from_reader(Input::open(path)?);
+        let headers = rdr.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|h| h.eq_ignore_ascii_case(name))
+                .ok_or(format!("The region mask {path:?} has no {name} column"))
+        };
+        let (lon_column, lat_column, region_column) =
+            (column("Longitude")?, column("Latitude")?, column("Region")?);
+
+        let mut names: Vec<String> = Vec::new();
+        let mut centers: Vec<(f64, f64, usize)> = Vec::new();
+        for row in rdr.records() {
+            let row = row?;
+            let region = &row[region_column];
+            if region.is_empty() {
+                continue;
+            }
+            let index = match names.iter().position(|name| name == region) {
+                Some(index) => index,
+                None => {
+                    names.push(region.to_string());
+                    names.len() - 1
+                }
+            };
+            centers.push((row[lon_column].parse()?, row[lat_column].parse()?, index));
+        }
+        if centers.is_empty() {
+            Err(format!("The region mask {path:?} has no cells in a region"))?
+        }
+
+        let spacing = |coordinates: Vec<f64>| {
+            let mut coordinates = coordinates;
+            coordinates.sort_by(f64::total_cmp);
+            coordinates
+                .windows(2)
+                .map(|pair| pair[1] - pair[0])
+                .filter(|spacing| *spacing > 1.0e-9)
+                .fold(f64::INFINITY, f64::min)
+        };
+        let grid_size = spacing(centers.iter().map(|c| c.0).collect())
+            .min(spacing(centers.iter().map(|c| c.1).collect()));
+        if !grid_size.is_finite() {
+            Err(format!(
+                "The size of the cells of the region mask {path:?} can't be found from a single cell"
+            ))?
+        }
+
+        let origin = (
+            centers[0].0 - grid_size / 2.0,
+            centers[0].1 - grid_size / 2.0,
+        );
+        let is_centered = |coordinate: f64, origin: f64| {
+            let cells = (coordinate - origin) / grid_size;
+            (cells - cells.floor() - 0.5).abs() < 1.0e-6
+        };
+        if let Some((lon, lat, _)) = centers
+            .iter()
+            .find(|(lon, lat, _)| !is_centered(*lon, origin.0) || !is_centered(*lat, origin.1))
+        {
+            Err(format!(
+                "The cell centered on ({lon}, {lat}) of the region mask {path:?} isn't on the grid of {grid_size} degree cells"
+            ))?
+        }
+
+        let cells: HashMap<(i64, i64), usize> = centers
+            .into_iter()
+            .map(|(lon, lat, index)| (mask_cell(lon, lat, grid_size, origin), index))
+            .collect();
+        log::info!(
+            "There are {} cells of {grid_size} degrees in the regions {}",
+            cells.len(),
+            names.join(", ")
+        );
+
+        Ok(Regions {
+            names,
+            shapes: Shapes::Mask {
+                grid_size,
+                origin,
+                cells,
+            },
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The indices of the regions containing the point.
+    pub fn containing(&self, longitude: f64, latitude: f64) -> Vec<usize> {
+        match &self.shapes {
+            Shapes::Polygons(shapes) => shapes
+                .iter()
+                .enumerate()
+                .filter(|(_, polygons)| polygons.iter().any(|p| p.contains(longitude, latitude)))
+                .map(|(index, _)| index)
+                .collect(),
+            Shapes::Mask {
+                grid_size,
+                origin,
+                cells,
+            } => cells
+                .get(&mask_cell(longitude, latitude, *grid_size, *origin))
+                .copied()
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    /// Whether the point is in any of the regions.
+    pub fn contains(&self, longitude: f64, latitude: f64) -> bool {
+        !self.containing(longitude, latitude).is_empty()
+    }
+}
+
+/// The polygon of the GeoJSON coordinates of its rings.
+fn geojson_polygon(coordinates: &Value) -> Result<Polygon, Box<dyn Error>> {
+    let rings = coordinates
+        .as_array()
+        .ok_or("A polygon has no coordinates")?
+        .iter()
+        .map(|ring| {
+            ring.as_array()
+                .ok_or("A polygon ring is not an array")?
+                .iter()
+                .map(|point| match (point[0].as_f64(), point[1].as_f64()) {
+                    (Some(lon), Some(lat)) => Ok((lon, lat)),
+                    _ => Err(format!("The polygon point {point} is not a position").into()),
+                })
+                .collect::<Result<Vec<(f64, f64)>, Box<dyn Error>>>()
+        })
+        .collect::<Result<_, _>>()?;
+    Polygon::new(rings)
+}
+
+/// The polygons of a WKT POLYGON or MULTIPOLYGON, with any SRID prefix and any
+/// Z or M values ignored.
+fn wkt_polygons(wkt: &str) -> Result<Vec<Polygon>, Box<dyn Error>> {
+    let wkt = wkt.rsplit(';').next().unwrap_or(wkt).trim();
+    let keyword: String = wkt
+        .chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .collect::<String>()
+        .to_uppercase();
+    let ring_depth = match keyword.as_str() {
+        "POLYGON" => 2,
+        "MULTIPOLYGON" => 3,
+        _ => Err(format!(
+            "Expected a WKT POLYGON or MULTIPOLYGON, not {wkt:?}"
+        ))?,
+    };
+
+    let mut polygons: Vec<Polygon> = Vec::new();
+    let mut rings: Vec<Vec<(f64, f64)>> = Vec::new();
+    let mut ring_text = String::new();
+    let mut depth = 0;
+    for c in wkt[keyword.len()..].chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                if depth == ring_depth {
+                    let ring = ring_text
+                        .split(',')
+                        .map(|point| {
+                            let mut values = point.split_whitespace().map(str::parse::<f64>);
+                            match (values.next(), values.next()) {
+                                (Some(Ok(lon)), Some(Ok(lat))) => Ok((lon, lat)),
+                                _ => Err(format!("The WKT point {point:?} is not a position")),
+                            }
+                        })
+                        .collect::<Result<_, _>>()?;
+                    rings.push(ring);
+                    ring_text.clear();
+                } else if depth == ring_depth - 1 {
+                    polygons.push(Polygon::new(std::mem::take(&mut rings))?);
+                }
+                depth -= 1;
+            }
+            _ if depth == ring_depth => ring_text.push(c),
+            _ => {}
+        }
+    }
+    if depth != 0 || polygons.is_empty() {
+        Err(format!("The WKT {wkt:?} is incomplete"))?
+    }
+
+    Ok(polygons)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::inputs::regions::Regions;
+
+    #[test]
+    /// Points are looked up in the cells of the mask.
+    fn mask_regions() {
+        let path = std::env::temp_dir().join(format!("grid_test_mask_{}.csv", std::process::id()));
+        fs::write(
+            &path,
+            "# A land/sea mask\nLongitude,Latitude,Region\n\
+             0.25,0.25,sea\n0.75,0.25,land\n0.25,0.75,\n0.75,0.75,land\n",
+        )
+        .unwrap();
+        let regions = Regions::read_mask(&path).unwrap();
+        assert_eq!(regions.names, ["sea", "land"]);
+        assert_eq!(regions.containing(0.1, 0.4), [0]);
+        assert_eq!(regions.containing(0.9, 0.9), [1]);
+        assert!(!regions.contains(0.3, 0.6));
+        assert!(!regions.contains(1.2, 0.2));
+
+        // The cells needn't start at a multiple of their size, but must be on one grid
+        fs::write(
+            &path,
+            "Longitude,Latitude,Region\n0.0,0.0,sea\n0.5,0.0,land\n",
+        )
+        .unwrap();
+        let regions = Regions::read_mask(&path).unwrap();
+        assert_eq!(regions.containing(-0.2, 0.2), [0]);
+        assert_eq!(regions.containing(0.3, -0.2), [1]);
+        assert!(!regions.contains(0.8, 0.0));
+        fs::write(
+            &path,
+            "Longitude,Latitude,Region\n0.0,0.0,sea\n0.5,0.0,land\n0.7,0.0,land\n",
+        )
+        .unwrap();
+        assert!(Regions::read_mask(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    /// Points are tested against the polygons, with holes left out.
+    fn polygon_regions() {
+        let geojson = r#"{"type": "FeatureCollection", "features": [
+            {"type": "Feature", "properties": {"name": "square"},
+             "geometry": {"type": "Polygon", "coordinates": [
+                [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+                [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]]]}},
+            {"type": "Feature", "properties": {},
+             "geometry": {"type": "MultiPolygon", "coordinates": [
+                [[[20, 0], [30, 0], [25, 10], [20, 0]]],
+                [[[5, 5], [15, 5], [15, 15], [5, 15], [5, 5]]]]}}]}"#;
+        let regions = Regions::from_geojson(geojson).unwrap();
+        assert_eq!(regions.names, ["square", "region-2"]);
+        assert_eq!(regions.containing(2.0, 2.0), [0]);
+        assert_eq!(regions.containing(5.0, 4.5), Vec::<usize>::new());
+        assert_eq!(regions.containing(8.0, 8.0), [0, 1]);
+        assert_eq!(regions.containing(25.0, 5.0), [1]);
+        assert!(!regions.contains(-1.0, 5.0));
+
+        let wkt = "# Study areas\n\
+            square\tPOLYGON ((0 0, 10 0, 10 10, 0 10, 0 0), (4 4, 6 4, 6 6, 4 6, 4 4))\n\
+            SRID=4326;MULTIPOLYGON (((20 0, 30 0, 25 10, 20 0)), ((5 5, 15 5, 15 15, 5 15, 5 5)))\n";
+        assert_eq!(Regions::from_wkt(wkt).unwrap(), regions);
+
+        assert!(Regions::from_wkt("POLYGON ((0 0, 10 0, 10 10").is_err());
+        assert!(Regions::from_wkt("POINT (1 2)").is_err());
+
+        // Every ring, including the holes, needs at least three points
+        let empty_hole = r#"{"type": "Polygon", "coordinates": [
+            [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]], []]}"#;
+        assert!(Regions::from_geojson(empty_hole).is_err());
+    }
+}
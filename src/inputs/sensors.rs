@@ -0,0 +1,456 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{BufRead, BufReader},
+    path::Path,
+};
+
+use crate::inputs::{
+    compression::{is_stdio, Input},
+    ReadRecord,
+};
+
+/// The imaging instruments whose retrievals we know how to grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instrument {
+    Ahi,
+    Viirs,
+    Abi,
+}
+
+impl Instrument {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instrument::Ahi => "AHI",
+            Instrument::Viirs => "VIIRS",
+            Instrument::Abi => "ABI",
+        }
+    }
+
+    /// Filename/attribute tokens which identify the instrument.
+    fn tokens(&self) -> &'static [&'static str] {
+        match self {
+            Instrument::Ahi => &["ahi", "himawari"],
+            Instrument::Viirs => &["viirs"],
+            Instrument::Abi => &["abi", "goes"],
+        }
+    }
+}
+
+/// The satellites carrying the supported instruments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Himawari8,
+    Himawari9,
+    Snpp,
+    Noaa20,
+    Noaa21,
+    Goes16,
+    Goes17,
+    Goes18,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 8] = [
+        Platform::Himawari8,
+        Platform::Himawari9,
+        Platform::Snpp,
+        Platform::Noaa20,
+        Platform::Noaa21,
+        Platform::Goes16,
+        Platform::Goes17,
+        Platform::Goes18,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Himawari8 => "Himawari-8",
+            Platform::Himawari9 => "Himawari-9",
+            Platform::Snpp => "SNPP",
+            Platform::Noaa20 => "NOAA-20",
+            Platform::Noaa21 => "NOAA-21",
+            Platform::Goes16 => "GOES-16",
+            Platform::Goes17 => "GOES-17",
+            Platform::Goes18 => "GOES-18",
+        }
+    }
+
+    /// The short name used for the `--satellite` option.
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            Platform::Himawari8 => "him8",
+            Platform::Himawari9 => "him9",
+            Platform::Snpp => "snpp",
+            Platform::Noaa20 => "noaa20",
+            Platform::Noaa21 => "noaa21",
+            Platform::Goes16 => "goes16",
+            Platform::Goes17 => "goes17",
+            Platform::Goes18 => "goes18",
+        }
+    }
+
+    pub fn from_short_name(short_name: &str) -> Option<Platform> {
+        Platform::ALL
+            .into_iter()
+            .find(|p| p.short_name() == short_name)
+    }
+
+    pub fn instrument(&self) -> Instrument {
+        match self {
+            Platform::Himawari8 | Platform::Himawari9 => Instrument::Ahi,
+            Platform::Snpp | Platform::Noaa20 | Platform::Noaa21 => Instrument::Viirs,
+            Platform::Goes16 | Platform::Goes17 | Platform::Goes18 => Instrument::Abi,
+        }
+    }
+
+    /// Filename/attribute tokens which identify the platform. These are compared
+    /// against the lowercased text with all separators removed.
+    fn tokens(&self) -> &'static [&'static str] {
+        match self {
+            Platform::Himawari8 => &["himawari8", "him8", "h08"],
+            Platform::Himawari9 => &["himawari9", "him9", "h09"],
+            Platform::Snpp => &["snpp", "suominpp", "npp"],
+            Platform::Noaa20 => &["noaa20", "n20", "j01", "jpss1"],
+            Platform::Noaa21 => &["noaa21", "n21", "j02", "jpss2"],
+            Platform::Goes16 => &["goes16", "g16"],
+            Platform::Goes17 => &["goes17", "g17"],
+            Platform::Goes18 => &["goes18", "g18"],
+        }
+    }
+
+    /// The sub-satellite longitude of the geostationary platforms.
+    pub fn sub_satellite_longitude(&self) -> Option<f64> {
+        match self {
+            Platform::Himawari8 | Platform::Himawari9 => Some(140.7),
+            Platform::Goes16 => Some(-75.2),
+            Platform::Goes17 | Platform::Goes18 => Some(-137.2),
+            Platform::Snpp | Platform::Noaa20 | Platform::Noaa21 => None,
+        }
+    }
+}
+
+/// A detected sensor. The platform may be unknown when only the instrument
+/// can be identified, as is the case for e.g. "viirs_cloud_top_height.csv".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sensor {
+    pub instrument: Instrument,
+    pub platform: Option<Platform>,
+}
+
+impl From<Platform> for Sensor {
+    fn from(platform: Platform) -> Self {
+        Sensor {
+            instrument: platform.instrument(),
+            platform: Some(platform),
+        }
+    }
+}
+
+impl fmt::Display for Sensor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.platform {
+            Some(platform) => write!(f, "{} {}", platform.name(), self.instrument.name()),
+            None => write!(f, "{}", self.instrument.name()),
+        }
+    }
+}
+
+/// The lon/lat bounding box of a grid, in degrees. For grids crossing the
+/// antimeridian `east` is greater than 180.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridExtent {
+    pub west: f64,
+    pub east: f64,
+    pub south: f64,
+    pub north: f64,
+}
+
+impl GridExtent {
+    pub const GLOBAL: GridExtent = GridExtent {
+        west: -180.0,
+        east: 180.0,
+        south: -90.0,
+        north: 90.0,
+    };
+}
+
+impl fmt::Display for GridExtent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.west, self.east, self.south, self.north
+        )
+    }
+}
+
+/// The SensorProfile struct holds the defaults that follow from the sensor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorProfile {
+    pub sensor: Sensor,
+    /// Native (nadir) resolution of the retrieval, in km.
+    pub native_resolution: f64,
+    /// Altitude of the satellite, in km.
+    pub altitude: f64,
+    /// Grid size suited to the sensor, in degrees, used with --sensor-gridsize.
+    pub grid_size: f64,
+    /// Valid range of the retrieved heights, in metres.
+    pub valid_heights: (i64, i64),
+    pub grid_extent: GridExtent,
+}
+
+/// Geostationary imagers see roughly this far (in degrees of great-circle
+/// distance) from the sub-satellite point.
+const GEO_VIEW_LIMIT: f64 = 81.0;
+
+impl SensorProfile {
+    pub fn new(sensor: Sensor) -> Self {
+        let native_resolution = match sensor.instrument {
+            Instrument::Ahi | Instrument::Abi => 2.0,
+            Instrument::Viirs => 0.75,
+        };
+        let altitude = match sensor.instrument {
+            Instrument::Ahi | Instrument::Abi => 35_786.0,
+            Instrument::Viirs => 829.0,
+        };
+        let grid_size = match sensor.instrument {
+            Instrument::Ahi | Instrument::Abi => 0.5,
+            Instrument::Viirs => 0.25,
+        };
+        let grid_extent = match sensor.platform.and_then(|p| p.sub_satellite_longitude()) {
+            Some(sub_lon) => {
+                let west = sub_lon - GEO_VIEW_LIMIT;
+                // Keep the western edge in [-180, 180), which may put the
+                // eastern edge past the antimeridian.
+                let west = if west < -180.0 { west + 360.0 } else { west };
+                GridExtent {
+                    west,
+                    east: west + 2.0 * GEO_VIEW_LIMIT,
+                    south: -GEO_VIEW_LIMIT,
+                    north: GEO_VIEW_LIMIT,
+                }
+            }
+            None => GridExtent::GLOBAL,
+        };
+
+        SensorProfile {
+            sensor,
+            native_resolution,
+            altitude,
+            grid_size,
+            valid_heights: (0, 20_000),
+            grid_extent,
+        }
+    }
+
+    /// Whether a record lies inside the valid ranges of this sensor. The records
+    /// outside them are only dropped with --sensor-ranges.
+    pub fn is_valid(&self, record: &ReadRecord) -> bool {
+        let (min_height, max_height) = self.valid_heights;
+        (-180.0..=180.0).contains(&record.longitude)
+            && (-90.0..=90.0).contains(&record.latitude)
+            && (min_height..=max_height).contains(&record.height)
+    }
+}
+
+/// Strip the text down to lowercase alphanumeric runs, so that e.g. "NOAA-20",
+/// "noaa_20" and "NOAA20" all compare equal.
+fn normalize(text: &str) -> Vec<String> {
+    let lower = text.to_lowercase();
+    let words: Vec<&str> = lower
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    // Also join adjacent words, to catch tokens such as "noaa-20".
+    let mut tokens: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+    tokens.extend(words.windows(2).map(|w| w.concat()));
+    tokens
+}
+
+/// Detect the sensor from a piece of text, such as a filename or the value of a
+/// file attribute. The platform is preferred over the instrument as it implies it.
+pub fn detect_from_text(text: &str) -> Option<Sensor> {
+    let tokens = normalize(text);
+
+    for platform in Platform::ALL {
+        if platform
+            .tokens()
+            .iter()
+            .any(|t| tokens.iter().any(|w| w == t))
+        {
+            return Some(Sensor::from(platform));
+        }
+    }
+
+    for instrument in [Instrument::Ahi, Instrument::Viirs, Instrument::Abi] {
+        if instrument
+            .tokens()
+            .iter()
+            .any(|t| tokens.iter().any(|w| w == t))
+        {
+            return Some(Sensor {
+                instrument,
+                platform: None,
+            });
+        }
+    }
+
+    None
+}
+
+/// Reads the attributes of a csv file, which are stored as leading comment
+/// lines of the form "# key: value" (or "# key = value").
+pub fn read_attributes(file_path: &Path) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let reader = BufReader::new(Input::open(file_path)?);
+    let mut attributes = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        let Some(comment) = line.trim_start().strip_prefix('#') else {
+            break;
+        };
+        if let Some((key, value)) = comment.split_once([':', '=']) {
+            attributes.push((key.trim().to_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    Ok(attributes)
+}
+
+/// Detects the sensor of a single input file, using its attributes first
+/// and falling back to its filename.
+pub fn detect_sensor(file_path: &Path) -> Result<Option<Sensor>, Box<dyn Error>> {
+    // Stdin can only be read once, by the reader
+    if is_stdio(file_path) {
+        return Ok(None);
+    }
+
+    const SENSOR_KEYS: [&str; 5] = ["platform", "satellite", "instrument", "sensor", "source"];
+
+    let attributes = read_attributes(file_path)?;
+    let mut from_attributes: Option<Sensor> = None;
+    for (key, value) in &attributes {
+        if !SENSOR_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        if let Some(sensor) = detect_from_text(value) {
+            // A platform trumps an instrument-only match from another attribute.
+            if from_attributes.is_none_or(|s| s.platform.is_none()) {
+                from_attributes = Some(sensor);
+            }
+        }
+    }
+    if from_attributes.is_some() {
+        return Ok(from_attributes);
+    }
+
+    let file_name = file_path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok(detect_from_text(&file_name))
+}
+
+/// Detects the sensor common to all of the input files. Files whose sensor
+/// can't be detected are ignored, but conflicting detections are an error.
+pub fn detect_sensors<P: AsRef<Path>>(files: &[P]) -> Result<Option<Sensor>, Box<dyn Error>> {
+    let mut detected: Option<Sensor> = None;
+
+    for file_path in files {
+        let file_path = file_path.as_ref();
+        let Some(sensor) = detect_sensor(file_path)? else {
+            log::debug!("Could not detect the sensor of {file_path:?}");
+            continue;
+        };
+        log::debug!("Detected sensor {sensor} for {file_path:?}");
+
+        detected = match detected {
+            None => Some(sensor),
+            Some(prev) if prev.instrument != sensor.instrument => Err(format!(
+                "Input files are from different instruments ({prev} and {sensor}), \
+                     use --satellite to choose one."
+            ))?,
+            Some(prev) => match (prev.platform, sensor.platform) {
+                (Some(a), Some(b)) if a != b => Err(format!(
+                    "Input files are from different platforms ({prev} and {sensor}), \
+                     use --satellite to choose one."
+                ))?,
+                (Some(_), _) => Some(prev),
+                (None, _) => Some(sensor),
+            },
+        };
+    }
+
+    Ok(detected)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inputs::sensors::{detect_from_text, Instrument, Platform, Sensor, SensorProfile};
+    use crate::ReadRecord;
+
+    #[test]
+    /// Platforms are detected from the usual granule naming conventions.
+    fn detect_platform_from_filenames() {
+        let cases = [
+            ("HS_H08_20230901_0000_B13_FLDK.csv", Platform::Himawari8),
+            ("himawari9_cloud_top_height.csv", Platform::Himawari9),
+            ("CLDPROP_L2_VIIRS_SNPP.A2023244.csv", Platform::Snpp),
+            ("viirs_j01_cloud_top_height.csv", Platform::Noaa20),
+            ("NOAA-21_viirs_cth.csv", Platform::Noaa21),
+            ("OR_ABI-L2-ACHAF-M6_G16_s2023.csv", Platform::Goes16),
+            ("goes18_cth.csv", Platform::Goes18),
+        ];
+        for (file_name, platform) in cases {
+            assert_eq!(
+                detect_from_text(file_name),
+                Some(Sensor::from(platform)),
+                "{file_name}"
+            );
+        }
+    }
+
+    #[test]
+    /// Only the instrument is known for the sample data.
+    fn detect_instrument_only() {
+        assert_eq!(
+            detect_from_text("viirs_cloud_top_height.csv"),
+            Some(Sensor {
+                instrument: Instrument::Viirs,
+                platform: None
+            })
+        );
+        assert_eq!(detect_from_text("cloud_top_height.csv"), None);
+    }
+
+    #[test]
+    /// The geostationary profiles are centred on the sub-satellite point.
+    fn geo_profile_extent() {
+        let profile = SensorProfile::new(Sensor::from(Platform::Himawari9));
+        assert_eq!(profile.grid_extent.west, 140.7 - 81.0);
+        assert_eq!(profile.grid_extent.east, 140.7 + 81.0);
+
+        let profile = SensorProfile::new(Sensor::from(Platform::Goes17));
+        assert!(profile.grid_extent.west >= -180.0);
+        assert!(profile.grid_extent.east > 180.0);
+    }
+
+    #[test]
+    /// Records outside the valid ranges are rejected.
+    fn profile_valid_ranges() {
+        let profile = SensorProfile::new(Sensor::from(Platform::Noaa20));
+        let mut record = ReadRecord {
+            longitude: 100.0,
+            latitude: 35.2,
+            height: 12345,
+            footprint: None,
+        };
+        assert!(profile.is_valid(&record));
+        record.height = -999;
+        assert!(!profile.is_valid(&record));
+        record.height = 12345;
+        record.latitude = 91.0;
+        assert!(!profile.is_valid(&record));
+    }
+}
@@ -1,124 +1,199 @@
 // use log::{debug, error, info, trace, warn, LevelFilter, SetLoggerError};
-use log::{LevelFilter, SetLoggerError};
+use indicatif::{MultiProgress, ProgressDrawTarget};
+use log::{LevelFilter, Record};
 use log4rs::{
     append::{
         console::{ConsoleAppender, Target},
         file::FileAppender,
+        Append,
     },
-    config::{Appender, Config, Root},
-    encode::pattern::PatternEncoder,
-    filter::threshold::ThresholdFilter,
-    Handle
+    config::{Appender, Config, Logger, Root},
+    encode::{json::JsonEncoder, pattern::PatternEncoder, Encode},
+    Handle,
 };
-use std::path::PathBuf;
-use chrono::prelude::*;
-// use chrono::offset::LocalResult;
-
-
-pub fn config_logger(verbosity: &u8, log_file: Option<&PathBuf>) -> Result<Handle, SetLoggerError> {
-
-    log::info!("The verbosity is {verbosity}");
-
-    let levels = [
-        LevelFilter::Error,
-        LevelFilter::Warn,
-        LevelFilter::Info,
-        LevelFilter::Debug,
-        LevelFilter::Trace,
-    ];
-    // let my_index: usize = verbosity.clone() as usize + 2;
-    let my_index: usize = *verbosity as usize + 2;
-    let my_index: usize = match my_index {
-        // v if v < 2 => 2 as usize,
-        v if v > 4 => 4_usize,
-        _ => my_index,
-        // _ => verbosity.clone() as usize
-    };
-    log::debug!("my_index is {my_index}");
-    let level = levels[my_index];
-    log::debug!("The level is {level}");
-
-    // Figure out what to do with the log file...
-    // TODO: Decide whether to have the default be a log file, or no log file.
-
-    let dt: DateTime<Utc> = Utc::now();
-    let dt_str: String = dt.format("%Y%m%dT%H%M%SZ").to_string();
-    let logname = format!("data/grid_test.{dt_str}.log");
-    log::info!("Creating log file = {:?}", logname);
-
-    let binding = &PathBuf::from(logname);
-    let file_path: &PathBuf = match log_file {
-        Some(f) => f,
-        None => binding,
-    };
-    log::info!("The log filename is {file_path:?}");
-
-    // Set the log output pattern
-    // Pattern: https://docs.rs/log4rs/*/log4rs/encode/pattern/index.html
-    let log_output_pattern = match level {
-        LevelFilter::Trace => "{d(%Y-%m-%d %H:%M:%S)}: {l:5} : {M}::{f}:{L:4} : {m}\n",
-        _ => "{l:5} : {m}\n",
-    };
-
-    // Build a stdout logger.
-    let stdout = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            log_output_pattern,
-        )))
-        .target(Target::Stdout).build();
+use std::{error::Error, path::PathBuf, str::FromStr, sync::OnceLock};
 
-    // Build a stderr logger.
-    let stderr = ConsoleAppender::builder()
-        .encoder(Box::new(PatternEncoder::new(
-            log_output_pattern,
-        )))
-        .target(Target::Stderr).build();
+/// The format of the log messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// One JSON object per line, for log aggregation.
+    Json,
+}
+
+impl LogFormat {
+    pub fn from_name(name: &str) -> Option<LogFormat> {
+        match name {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// The LogConfig struct holds the logging settings given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    /// Number of -v flags given.
+    pub verbosity: u8,
+    /// Only log errors, regardless of the verbosity.
+    pub quiet: bool,
+    /// Also write the log messages to this file.
+    pub log_file: Option<PathBuf>,
+    pub format: LogFormat,
+    /// Levels for individual modules, overriding the verbosity.
+    pub module_levels: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            verbosity: 0,
+            quiet: false,
+            log_file: None,
+            format: LogFormat::Text,
+            module_levels: Vec::new(),
+        }
+    }
+}
 
-    // Build a file logger.
-    let logfile = FileAppender::builder()
+impl LogConfig {
+    /// The level for the root logger. Warnings are shown by default, and each -v
+    /// increases the verbosity by one level: -v=INFO, -vv=DEBUG, -vvv=TRACE.
+    pub fn level(&self) -> LevelFilter {
+        if self.quiet {
+            return LevelFilter::Error;
+        }
+        let levels = [
+            LevelFilter::Warn,
+            LevelFilter::Info,
+            LevelFilter::Debug,
+            LevelFilter::Trace,
+        ];
+        levels[(self.verbosity as usize).min(levels.len() - 1)]
+    }
+
+    fn encoder(&self) -> Box<dyn Encode> {
         // Pattern: https://docs.rs/log4rs/*/log4rs/encode/pattern/index.html
-        .encoder(Box::new(PatternEncoder::new(
-            log_output_pattern,
-        )))
-        .build(file_path)
-        .unwrap();
-
-    // Log Trace level output to file where trace is the default level
-    // and the programmatically specified level to stderr.
-    let config = Config::builder()
-        .appender(Appender::builder().build("logfile", Box::new(logfile)))
-        .appender(
-            Appender::builder()
-                .filter(Box::new(ThresholdFilter::new(level)))
-                .build("stdout", Box::new(stdout)),
-        )
-        .appender(
-            Appender::builder()
-                .filter(Box::new(ThresholdFilter::new(level)))
-                .build("stderr", Box::new(stderr)),
-        )
-        .build(
-            Root::builder()
-                .appender("logfile")
-                .appender("stdout")
-                // .appender("stderr")
-                .build(level),
+        let log_output_pattern = match self.level() {
+            LevelFilter::Trace => "{d(%Y-%m-%d %H:%M:%S)}: {l:5} : {M}::{f}:{L:4} : {m}\n",
+            _ => "{l:5} : {m}\n",
+        };
+        match self.format {
+            LogFormat::Text => Box::new(PatternEncoder::new(log_output_pattern)),
+            LogFormat::Json => Box::new(JsonEncoder::new()),
+        }
+    }
+}
+
+/// Parses a per-module log level of the form "MODULE=LEVEL", e.g. "grid_test::inputs=debug".
+pub fn parse_module_level(module_level: &str) -> Result<(String, LevelFilter), String> {
+    let (module, level) = module_level.split_once('=').ok_or(format!(
+        "Invalid module log level {module_level:?}, expected MODULE=LEVEL"
+    ))?;
+    let level = LevelFilter::from_str(level.trim()).map_err(|_| {
+        format!(
+            "Invalid log level {level:?}, expected one of off, error, warn, info, debug or trace"
         )
-        .unwrap();
+    })?;
+    Ok((module.trim().to_string(), level))
+}
+
+/// The progress bars drawn on stderr. They are hidden while a message is
+/// logged to stderr, so the message isn't torn by them.
+pub fn progress_bars() -> &'static MultiProgress {
+    static BARS: OnceLock<MultiProgress> = OnceLock::new();
+    BARS.get_or_init(|| MultiProgress::with_draw_target(ProgressDrawTarget::stderr()))
+}
 
-    // Use this to change log levels at runtime.
-    // This means you can change the default log level to trace
-    // if you are trying to debug an issue and need more logs on then turn it off
-    // once you are done.
+/// Logs to stderr around any progress bars.
+#[derive(Debug)]
+struct StderrAppender {
+    console: ConsoleAppender,
+}
+
+impl Append for StderrAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        progress_bars().suspend(|| self.console.append(record))
+    }
+
+    fn flush(&self) {
+        self.console.flush();
+    }
+}
+
+/// Configures the logger. The log messages go to stderr, keeping stdout free
+/// for data, and to the log file if one is given.
+pub fn config_logger(log_config: &LogConfig) -> Result<Handle, Box<dyn Error>> {
+    let level = log_config.level();
+
+    // Build a stderr logger.
+    let stderr = ConsoleAppender::builder()
+        .encoder(log_config.encoder())
+        .target(Target::Stderr)
+        .build();
+
+    let mut config = Config::builder().appender(
+        Appender::builder().build("stderr", Box::new(StderrAppender { console: stderr })),
+    );
+    let mut root = Root::builder().appender("stderr");
+
+    // Build a file logger, if asked for.
+    if let Some(file_path) = &log_config.log_file {
+        let logfile = FileAppender::builder()
+            .encoder(log_config.encoder())
+            .build(file_path)
+            .map_err(|e| format!("Could not create the log file {file_path:?}: {e}"))?;
+        config = config.appender(Appender::builder().build("logfile", Box::new(logfile)));
+        root = root.appender("logfile");
+    }
+
+    // The module levels override the root level, whether more or less verbose.
+    for (module, module_level) in &log_config.module_levels {
+        config = config.logger(Logger::builder().build(module, *module_level));
+    }
+
+    let config = config.build(root.build(level))?;
+
+    // Use the handle to change log levels at runtime.
     let handle = log4rs::init_config(config)?;
-    // handle.set_config(config);
 
-    log::error!("args() Goes to stderr and file");
-    log::warn!("args() Goes to stderr and file");
-    log::info!("args() Goes to stderr and file");
-    log::debug!("args() Goes to file only");
-    log::trace!("args() Goes to file only");
+    log::debug!("The log level is {level}");
+    if let Some(file_path) = &log_config.log_file {
+        log::info!("The log filename is {file_path:?}");
+    }
 
-    // SetLoggerError
     Ok(handle)
 }
+
+#[cfg(test)]
+mod tests {
+    use log::LevelFilter;
+
+    use crate::{parse_module_level, LogConfig};
+
+    #[test]
+    /// Each -v increases the level from WARN, and --quiet only shows errors.
+    fn log_levels() {
+        let mut log_config = LogConfig::default();
+        assert_eq!(log_config.level(), LevelFilter::Warn);
+        log_config.verbosity = 2;
+        assert_eq!(log_config.level(), LevelFilter::Debug);
+        log_config.verbosity = 10;
+        assert_eq!(log_config.level(), LevelFilter::Trace);
+        log_config.quiet = true;
+        assert_eq!(log_config.level(), LevelFilter::Error);
+    }
+
+    #[test]
+    /// Module levels are given as MODULE=LEVEL.
+    fn module_levels() {
+        assert_eq!(
+            parse_module_level("grid_test::inputs=debug"),
+            Ok(("grid_test::inputs".to_string(), LevelFilter::Debug))
+        );
+        assert!(parse_module_level("grid_test::inputs").is_err());
+        assert!(parse_module_level("grid_test=loud").is_err());
+    }
+}
@@ -1,26 +1,57 @@
-use std::{collections::HashMap, env, error::Error, path::PathBuf};
-use glob::glob;
-use walkdir::WalkDir;
+use clap::{parser::ValueSource, ArgMatches};
+use glob::Pattern;
+use std::{
+    collections::HashMap,
+    env,
+    error::Error,
+    mem,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 // use std::result::Result;
 
 // Looks for code in src/lib.rs
-use data::config_logger;
+use data::{config_logger, LogConfig, LogFormat};
+use log::LevelFilter;
 
 // Looks for code in src/args.rs
 pub mod args;
 
 // Looks for code in src/reads.rs
 pub mod inputs;
+use inputs::compression::{is_stdio, uncompressed_extension};
+use inputs::discovery::{self, DiscoveryOptions, SymlinkPolicy};
+use inputs::footprint::FootprintColumns;
+use inputs::granules::{self, TimeWindow, DEFAULT_TIME_PATTERNS};
+use inputs::gridded::GriddedOutput;
 use inputs::reads as read_csv;
-use read_csv::ReadRecord;
+use inputs::regions::Regions;
+use inputs::sensors::{self, GridExtent, Instrument, Platform, Sensor, SensorProfile};
+use read_csv::{FileReadSummary, ReadLimits, ReadRecord};
 
 // Looks for code in src/compute.rs
 pub mod compute;
-use compute::HeightData;
-use compute::MAX_RECORDS;
+use compute::compare;
+use compute::grids::Grid;
+use compute::means::{self, MeanTable, Weighting};
+use compute::remap::{self, RemapCell, RemapMethod};
+use compute::summaries::{self, RegionSummary};
+use compute::{GridOrder, HeightData, SortOrder};
+
+// Looks for code in src/progress.rs
+pub mod progress;
+use progress::{Progress, ProgressMode};
 
 // Looks for code in src/writes.rs
 pub mod outputs;
+use outputs::files::{self, ClobberPolicy};
+use outputs::geojson::{self, Geometry};
+use outputs::geotiff::{self, RasterFormat, RasterLayout, SampleType};
+use outputs::render::{self, Colormap, RenderOptions, RENDERED_STATISTICS};
+use outputs::report::{
+    GridSpec, InputFileReport, OutputReport, RunReport, StageTiming, TOOL_VERSION,
+};
+use outputs::writes::{CsvFormat, STATISTICS};
 use outputs::{writes as write_csv, WriteRecord};
 
 /// The main function
@@ -29,65 +60,826 @@ fn main() -> Result<(), Box<dyn Error>> {
     log::debug!("Initial sys_args is: {sys_args:?}");
     let args = args::args(&sys_args)?;
 
-    let verbosity: &u8 = args.get_one("verbosity").unwrap();
-    let log_file: Option<&PathBuf> = args.get_one("log_file");
+    let log_config = LogConfig {
+        verbosity: *args.get_one::<u8>("verbosity").unwrap(),
+        quiet: args.get_flag("quiet"),
+        log_file: args.get_one::<PathBuf>("log_file").cloned(),
+        format: LogFormat::from_name(args.get_one::<String>("log_format").unwrap()).unwrap(),
+        module_levels: args
+            .get_many::<(String, LevelFilter)>("log_level")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+    };
+    config_logger(&log_config)?;
+    let progress_mode = ProgressMode::detect(log_config.quiet);
+
+    let mut in_paths: Vec<&PathBuf> = args.get_many("in_file").unwrap_or_default().collect();
+    let file_list: Option<&PathBuf> = args.get_one("file_list");
+    let out_file: &PathBuf = args.get_one("out_file").unwrap();
+    let algorithm: &String = args.get_one("alg").unwrap();
+    let satellite: Option<&String> = args.get_one("satellite");
+    let report_file: Option<&PathBuf> = args.get_one("report");
+    let clobber = match args.get_flag("no_clobber") {
+        true => ClobberPolicy::NoClobber,
+        false => ClobberPolicy::Overwrite,
+    };
 
-    log::debug!("The verbosity is {verbosity}");
-    log::info!("The input log filename is {log_file:?}");
+    if let Some(paths) = args.get_many::<PathBuf>("compare") {
+        let paths: Vec<&PathBuf> = paths.collect();
+        return compare_outputs(&args, paths[0], paths[1], out_file, clobber);
+    }
+    if let Some(gridded_file) = args.get_one::<PathBuf>("render") {
+        return render_output(&args, gridded_file, out_file, clobber);
+    }
 
-    config_logger(verbosity, log_file)?;
+    // Each grid size of a list is written to its own output, named after the size
+    let mut grid_sizes: Vec<f64> = args
+        .get_many("grid_size")
+        .unwrap_or_default()
+        .copied()
+        .collect();
+    grid_sizes.sort_by(f64::total_cmp);
+    grid_sizes.dedup();
+    let out_files: Vec<PathBuf> = match grid_sizes.len() {
+        0 | 1 => vec![out_file.clone()],
+        _ if is_stdio(out_file) => Err("A list of grid sizes can't be written to stdout.")?,
+        _ => grid_sizes
+            .iter()
+            .map(|grid_size| files::suffixed_path(out_file, &format!("{grid_size}deg"), None))
+            .collect(),
+    };
 
-    // log::error!("main() Goes to stderr and file");
-    // log::warn!("main() Goes to stderr and file");
-    // log::info!("main() Goes to stderr and file");
-    // log::debug!("main() Goes to file only");
-    // log::trace!("main() Goes to file only");
+    // The tables of means are written alongside each output
+    let mean_tables: Vec<MeanTable> = args
+        .get_many::<String>("means")
+        .unwrap_or_default()
+        .map(|name| MeanTable::from_name(name).unwrap())
+        .collect();
+    let weighting = Weighting::from_name(args.get_one::<String>("weights").unwrap()).unwrap();
+    if !mean_tables.is_empty() && is_stdio(out_file) {
+        Err("--means can't be used when writing to stdout.")?
+    }
+    let region_stats = args.get_flag("region_stats");
+    if region_stats && !args.contains_id("regions") && !args.contains_id("region_mask") {
+        Err("--region-stats needs the regions from --regions or --region-mask.")?
+    }
+    if region_stats && is_stdio(out_file) {
+        Err("--region-stats can't be used when writing to stdout.")?
+    }
 
-    // TODO: Restrict path depth...
-    log::debug!("Running the file glob...");
-    for entry in glob("data/**/*.csv").unwrap() {
-        match entry {
-            Ok(path) => log::debug!("\t{:?}", path.display()),
+    let mut csv_format = csv_format(&args);
+    if let Some(columns) = args.get_many::<String>("columns") {
+        csv_format.select_columns(&columns.cloned().collect::<Vec<String>>())?;
+    }
+    let raster_format = RasterFormat {
+        sample_type: SampleType::from_name(args.get_one::<String>("raster_type").unwrap()).unwrap(),
+        layout: RasterLayout::from_name(args.get_one::<String>("raster_layout").unwrap()).unwrap(),
+        nodata: *args.get_one("nodata").unwrap(),
+    };
 
-            // if the path matched but was unreadable,
-            // thereby preventing its contents from matching
-            Err(e) => log::debug!("\t{:?}", e),
+    // Check the outputs can be written before doing any work
+    for out_file in &out_files {
+        match uncompressed_extension(out_file).as_deref() {
+            Some("tif" | "tiff") => {
+                for path in geotiff::raster_files(out_file, &csv_format, &raster_format) {
+                    clobber.check(&path)?;
+                }
+            }
+            _ => clobber.check(out_file)?,
+        }
+        for table in &mean_tables {
+            clobber.check(&files::suffixed_path(out_file, table.name(), Some("csv")))?;
+        }
+        if region_stats {
+            clobber.check(&files::suffixed_path(out_file, "regions", Some("csv")))?;
         }
     }
-    log::debug!("Running the dir walk...");
-    const MAX_DIR_DEPTH: usize = 2;
-    for entry in WalkDir::new("data").min_depth(1).max_depth(MAX_DIR_DEPTH) {
-        log::debug!("\twalkdir has path {:?}", entry?.path().display());
+    if let Some(report_file) = report_file {
+        clobber.check(report_file)?;
     }
 
-    let in_files: Vec<&PathBuf> = args.get_many("in_file").unwrap().collect();
-    let out_file: &PathBuf = args.get_one("out_file").unwrap();
-    let algorithm: &String = args.get_one("alg").unwrap();
-    let grid_size: &f64 = args.get_one("grid_size").unwrap();
-
     log::info!("The alg is {algorithm}");
 
-    let csv_records: Vec<ReadRecord> = match algorithm.as_str() {
+    let start = Instant::now();
+    let mut discovery_options = DiscoveryOptions {
+        recursive: args.get_flag("recursive"),
+        max_depth: args.get_one("max_depth").copied(),
+        symlinks: SymlinkPolicy::from_name(args.get_one::<String>("symlinks").unwrap()).unwrap(),
+        ..DiscoveryOptions::default()
+    };
+    if let Some(patterns) = args.get_many::<String>("include") {
+        discovery_options.include = patterns
+            .map(|p| Pattern::new(p))
+            .collect::<Result<_, _>>()?;
+    }
+    if let Some(patterns) = args.get_many::<String>("exclude") {
+        discovery_options.exclude = patterns
+            .map(|p| Pattern::new(p))
+            .collect::<Result<_, _>>()?;
+    }
+    // Stdin can only be read once, for either the file list or an input
+    let stdin_list = file_list.is_some_and(|list_file| is_stdio(list_file));
+    let stdin_error = "Stdin can't be read for both the --file-list and an input file.";
+    if stdin_list && in_paths.iter().any(|path| is_stdio(path)) {
+        Err(stdin_error)?
+    }
+    let listed_paths: Vec<PathBuf> = match file_list {
+        Some(list_file) => granules::read_file_list(list_file)?,
+        None => Vec::new(),
+    };
+    if stdin_list && listed_paths.iter().any(|path| is_stdio(path)) {
+        Err(stdin_error)?
+    }
+    in_paths.extend(listed_paths.iter());
+
+    let time_window = TimeWindow {
+        start: args
+            .get_one::<String>("start")
+            .map(|t| granules::parse_window_time(t))
+            .transpose()?,
+        end: args
+            .get_one::<String>("end")
+            .map(|t| granules::parse_window_time(t))
+            .transpose()?,
+        patterns: match args.get_many::<String>("time_pattern") {
+            Some(patterns) => patterns.cloned().collect(),
+            None => DEFAULT_TIME_PATTERNS.map(String::from).to_vec(),
+        },
+        skip_untimed: args.get_flag("skip_untimed"),
+    };
+
+    let in_file_list: Vec<PathBuf> = discovery::discover_files(&in_paths, &discovery_options)?;
+    let in_file_list: Vec<PathBuf> = time_window.select(in_file_list)?;
+    if in_file_list.is_empty() && time_window.is_set() {
+        Err("None of the input files have a granule time between --start and --end.")?
+    }
+    if in_file_list.is_empty() {
+        Err("No input files were found.")?
+    }
+    let in_files: Vec<&PathBuf> = in_file_list.iter().collect();
+    for in_file in &in_files {
+        log::debug!("\t{in_file:?}");
+    }
+
+    // Gridded inputs are merged on their own grid, rather than binned as observations
+    let mut gridded_summaries: Vec<FileReadSummary> = Vec::new();
+    let gridded: Option<(f64, Vec<WriteRecord>)> = match args.get_flag("gridded") {
+        true => Some(read_gridded_inputs(&in_files, &mut gridded_summaries)?),
+        false => None,
+    };
+
+    // The --satellite option overrides the automatic sensor detection
+    let sensor: Option<Sensor> = match satellite {
+        Some(short_name) => Platform::from_short_name(short_name).map(Sensor::from),
+        None => sensors::detect_sensors(&in_files)?,
+    };
+    match sensor {
+        Some(sensor) => log::info!("The sensor is {sensor}"),
+        None => log::warn!("Could not detect the sensor, use --satellite to set it."),
+    }
+    let profile: Option<SensorProfile> = sensor.map(SensorProfile::new);
+
+    // Unless it is given, the grid size is that of gridded inputs, or of the sensor if asked for
+    if args.value_source("grid_size") != Some(ValueSource::CommandLine) {
+        match (&gridded, &profile) {
+            (Some((input_grid_size, _)), _) => grid_sizes = vec![*input_grid_size],
+            (None, Some(profile)) if args.get_flag("sensor_grid_size") => {
+                grid_sizes = vec![profile.grid_size]
+            }
+            _ => {}
+        }
+    }
+    let grid_size: &f64 = &grid_sizes[0];
+    log::info!("The grid size is {grid_size}");
+
+    // The coarser grid sizes are aggregated from cells which fit into all of them
+    let bin_grid_size: f64 = compute::common_grid_size(&grid_sizes)?;
+    if grid_sizes.len() > 1 {
+        log::info!("Binning into {bin_grid_size} degree cells for the grid sizes {grid_sizes:?}");
+    }
+
+    let resolution: f64 = *args.get_one("resolution").unwrap();
+    let grid = match args.get_one::<String>("grid").unwrap().as_str() {
+        "ease2" => Grid::Ease2 { resolution },
+        "sinusoidal" => Grid::Sinusoidal { resolution },
+        "polar-north" => Grid::PolarStereographic {
+            resolution,
+            true_scale_lat: 70.0,
+            central_lon: -45.0,
+        },
+        "polar-south" => Grid::PolarStereographic {
+            resolution,
+            true_scale_lat: -71.0,
+            central_lon: 0.0,
+        },
+        "lambert" => {
+            let params: Vec<f64> = args.get_many("lambert").unwrap().copied().collect();
+            let [lat_1, lat_2, lat_0, lon_0] = params[..] else {
+                Err("--lambert takes four values, LAT_1,LAT_2,LAT_0,LON_0")?
+            };
+            Grid::LambertConformal {
+                resolution,
+                lat_1,
+                lat_2,
+                lat_0,
+                lon_0,
+            }
+        }
+        "geostationary" => {
+            let platform = sensor.and_then(|s| s.platform);
+            let sub_lon: f64 = match args.get_one("sub_lon") {
+                Some(sub_lon) => *sub_lon,
+                None => platform.and_then(|p| p.sub_satellite_longitude()).ok_or(
+                    "The geostationary grid needs a geostationary --satellite or --sub-lon.",
+                )?,
+            };
+            let sweep_x = match args.get_one::<String>("sweep") {
+                Some(sweep) => sweep == "x",
+                None => sensor.is_some_and(|s| s.instrument == Instrument::Abi),
+            };
+            Grid::Geostationary {
+                resolution,
+                sub_lon,
+                sweep_x,
+            }
+        }
+        _ => Grid::LonLat {
+            grid_size: bin_grid_size,
+        },
+    };
+    log::info!("The grid is {grid}");
+
+    let mut report = RunReport::new(GridSpec {
+        grid: grid.to_string(),
+        grid_size: grid.resolution(),
+        sensor: sensor.map(|s| s.to_string()),
+        native_resolution_km: profile.as_ref().map(|p| p.native_resolution),
+        grid_extent: profile.as_ref().map(|p| p.grid_extent.to_string()),
+    });
+    report.set_arguments(&args);
+    report.add_timing("discovery", start);
+
+    // The radius of influence defaults to the size of a grid cell
+    let radius: f64 = args
+        .get_one("radius")
+        .copied()
+        .unwrap_or(grid_size * remap::KM_PER_DEGREE);
+    let remap_method: Option<RemapMethod> = match args.get_one::<String>("remap").unwrap().as_str()
+    {
+        "idw" => Some(RemapMethod::InverseDistance {
+            radius,
+            power: *args.get_one("power").unwrap(),
+        }),
+        "gaussian" => Some(RemapMethod::Gaussian {
+            radius,
+            sigma: args.get_one("sigma").copied().unwrap_or(radius / 2.0),
+        }),
+        "nearest" => Some(RemapMethod::Nearest { radius }),
+        "footprint" => Some(RemapMethod::Footprint),
+        _ => None,
+    };
+    let footprint_columns = match (
+        remap_method,
+        args.get_one::<String>("footprint").unwrap().as_str(),
+    ) {
+        (Some(RemapMethod::Footprint), "size") => FootprintColumns::Size,
+        (Some(RemapMethod::Footprint), _) => match &profile {
+            Some(profile) => FootprintColumns::Zenith {
+                nadir_resolution: profile.native_resolution,
+                altitude: profile.altitude,
+            },
+            None => {
+                Err("The sensor is needed to derive the footprints, use --satellite to set it.")?
+            }
+        },
+        _ => FootprintColumns::None,
+    };
+    if let Some(method) = &remap_method {
+        log::info!("The remapping method is {method:?}");
+    }
+    if !grid.is_lon_lat() && remap_method.is_some() {
+        Err("--remap is only supported on the lon/lat grid.")?
+    }
+    if !grid.is_lon_lat() && args.get_flag("full_grid") {
+        Err("--full-grid is only supported on the lon/lat grid.")?
+    }
+    if !grid.is_lon_lat()
+        && matches!(
+            uncompressed_extension(out_file).as_deref(),
+            Some("tif" | "tiff")
+        )
+    {
+        Err("GeoTIFF outputs are only supported on the lon/lat grid.")?
+    }
+    if !grid.is_lon_lat() && uncompressed_extension(out_file).as_deref() == Some("png") {
+        Err("PNG outputs are only supported on the lon/lat grid.")?
+    }
+    if !grid.is_lon_lat() && mean_tables.iter().any(|t| *t != MeanTable::Global) {
+        Err("The zonal and meridional means are only supported on the lon/lat grid.")?
+    }
+    if gridded.is_some() && (!grid.is_lon_lat() || remap_method.is_some()) {
+        Err("Gridded inputs can only be merged on the lon/lat grid, without --remap.")?
+    }
+    if gridded.is_some() && (args.contains_id("regions") || args.contains_id("region_mask")) {
+        Err("The regions select observations, so can't be used with gridded inputs.")?
+    }
+    if grid_sizes.len() > 1 && (!grid.is_lon_lat() || remap_method.is_some()) {
+        Err("A list of grid sizes is only supported when binning on the lon/lat grid.")?
+    }
+
+    let regions: Option<Regions> = match (
+        args.get_one::<PathBuf>("regions"),
+        args.get_one::<PathBuf>("region_mask"),
+    ) {
+        (Some(path), _) => Some(Regions::read(path)?),
+        (_, Some(path)) => Some(Regions::read_mask(path)?),
+        _ => None,
+    };
+    if let Some(regions) = &regions {
+        report.regions = regions.names.clone();
+    }
+    let mut region_summaries: Vec<RegionSummary> = regions
+        .as_ref()
+        .map_or(Vec::new(), RegionSummary::for_regions);
+    let mut outside_regions: usize = 0;
+
+    let memory_limit: Option<&usize> = args.get_one("memory_limit");
+    let read_limits = ReadLimits {
+        per_file: args.get_one("max_records").copied(),
+        total: args.get_one("max_records_total").copied(),
+        chunk_size: memory_limit.map(|limit| (limit / mem::size_of::<ReadRecord>()).max(1)),
+        footprint: footprint_columns,
+    };
+
+    // Each chunk of records is filtered and binned as it is read
+    let start = Instant::now();
+    let mut grid_dict: HashMap<String, HeightData> = HashMap::new();
+    let mut remap_cells: HashMap<(i64, i64), RemapCell> = HashMap::new();
+    let mut rejected: HashMap<PathBuf, usize> = HashMap::new();
+    let mut binning_time = Duration::ZERO;
+    // Only with --sensor-ranges are the records checked against the sensor's ranges
+    let valid_ranges = profile.as_ref().filter(|_| args.get_flag("sensor_ranges"));
+    if args.get_flag("sensor_ranges") && profile.is_none() {
+        log::warn!("The sensor is unknown, so no records are dropped by --sensor-ranges.");
+    }
+    let binning = Progress::items(progress_mode, "binning", 0);
+    let mut records_binned: u64 = 0;
+    let mut bin_chunk = |file_path: &Path,
+                         mut csv_records: Vec<ReadRecord>|
+     -> Result<(), Box<dyn Error>> {
+        let bin_start = Instant::now();
+        let num_records = csv_records.len() as u64;
+        binning.add_items(num_records);
+        if let Some(profile) = valid_ranges {
+            let num_read = csv_records.len();
+            csv_records.retain(|record| profile.is_valid(record));
+            *rejected.entry(file_path.to_path_buf()).or_default() += num_read - csv_records.len();
+        }
+        if let Some(regions) = &regions {
+            outside_regions +=
+                summaries::select_regions(&mut csv_records, regions, &mut region_summaries);
+        }
+        match &remap_method {
+            Some(method) => {
+                remap::remap_records(&mut remap_cells, &csv_records, *grid_size, method)
+            }
+            None => compute::bin_records_on_grid(&mut grid_dict, &csv_records, &grid)?,
+        }
+        binning_time += bin_start.elapsed();
+        records_binned += num_records;
+        binning.update(records_binned, num_records);
+        Ok(())
+    };
+
+    let total_bytes: u64 = in_files
+        .iter()
+        .map(|f| f.metadata().map_or(0, |m| m.len()))
+        .sum();
+    let progress = Progress::bytes(progress_mode, "reading", total_bytes, in_files.len() as u64);
+    let file_summaries: Vec<FileReadSummary> = match algorithm.as_str() {
+        // The gridded inputs have already been read
+        _ if gridded.is_some() => mem::take(&mut gridded_summaries),
         "serde" => {
             log::info!("We have chosen the serde branch.");
-            read_csv::read_using_csv_serde(&in_files, &MAX_RECORDS)?
+            read_csv::read_using_csv_serde(&in_files, &read_limits, &mut bin_chunk, &progress)?
         }
         "csv" => {
             log::info!("We have chosen the csv branch.");
-            read_csv::read_using_csv(&in_files, &MAX_RECORDS)?
+            read_csv::read_using_csv(&in_files, &read_limits, &mut bin_chunk, &progress)?
         }
         _ => {
             log::info!("No branch was chosen.");
             Vec::new()
         }
     };
+    progress.finish();
+    binning.finish();
+    report.timings.push(StageTiming {
+        stage: "reading".to_string(),
+        seconds: (start.elapsed() - binning_time).as_secs_f64(),
+    });
+    report.timings.push(StageTiming {
+        stage: "binning".to_string(),
+        seconds: binning_time.as_secs_f64(),
+    });
+
+    for summary in &file_summaries {
+        let mut input = InputFileReport::new(&summary.path)?;
+        input.records_read = summary.num_records;
+        input.records_rejected = rejected.get(&summary.path).copied().unwrap_or(0);
+        input.truncated = summary.truncated;
+        if report_file.is_some() {
+            input.compute_checksum()?;
+        }
+        report.records_read += input.records_read;
+        report.records_rejected += input.records_rejected;
+        report.inputs.push(input);
+    }
+    let num_records = report.records_read;
+    let num_rejected = report.records_rejected;
+    match &gridded {
+        Some((_, cells)) => log::info!(
+            "Read {num_records} records in {} gridded cells.",
+            cells.len()
+        ),
+        None => log::info!(
+            "Binned {num_records} records into {} grid cells.",
+            grid_dict.len().max(remap_cells.len())
+        ),
+    }
+
+    if num_rejected > 0 {
+        if let Some(profile) = &profile {
+            log::warn!(
+                "Rejected {num_rejected} records outside the valid ranges of {}",
+                profile.sensor
+            );
+        }
+    }
+
+    if regions.is_some() {
+        log::info!("Skipped {outside_regions} records outside the regions");
+    }
+
+    if !grid.is_lon_lat() && !args.contains_id("columns") {
+        csv_format.add_column("CellArea")?;
+    }
+    report.statistics = STATISTICS.map(String::from).to_vec();
+    let geometry = Geometry::from_name(args.get_one::<String>("geometry").unwrap()).unwrap();
+    let render_options = render_options(&args);
+    let extent = profile.map_or(GridExtent::GLOBAL, |p| p.grid_extent);
+
+    let output_grids: Vec<Grid> = match grid_sizes.len() {
+        1 => vec![grid],
+        _ => grid_sizes
+            .iter()
+            .map(|grid_size| Grid::LonLat {
+                grid_size: *grid_size,
+            })
+            .collect(),
+    };
+    for (output_grid, out_file) in output_grids.iter().zip(&out_files) {
+        let start = Instant::now();
+        let grid_size = &output_grid.resolution();
+        let aggregated_dict: HashMap<String, HeightData>;
+        let output_dict = match output_grids.len() == 1 || *grid_size == bin_grid_size {
+            true => &grid_dict,
+            false => {
+                aggregated_dict = compute::aggregate_cells(&grid_dict, *grid_size)?;
+                &aggregated_dict
+            }
+        };
+        let mut output_records: Vec<WriteRecord> = match (&gridded, &remap_method) {
+            (Some((input_grid_size, cells)), _) => {
+                compute::merge_cells(cells, *input_grid_size, *grid_size)?
+            }
+            (None, Some(method)) => remap::calc_stats(&remap_cells, *grid_size, method),
+            (None, None) => compute::calc_stats(output_dict)?,
+        };
+        let populated_cells = output_records.len();
+        let full_grid = args.get_flag("full_grid");
+        if full_grid {
+            let order =
+                GridOrder::from_name(args.get_one::<String>("grid_order").unwrap()).unwrap();
+            output_records = compute::fill_grid(output_records, *grid_size, &extent, order);
+        }
+        compute::set_cell_areas(&mut output_records, output_grid);
+        // A full grid is already in the order of the dense grid
+        if !full_grid || args.value_source("sort") == Some(ValueSource::CommandLine) {
+            let order = SortOrder::from_name(args.get_one::<String>("sort").unwrap()).unwrap();
+            compute::sort_records(&mut output_records, order);
+        }
+        report.add_timing("statistics", start);
+
+        // The metadata of each output describes its own grid
+        let start = Instant::now();
+        report.grid.grid = output_grid.to_string();
+        report.grid.grid_size = output_grid.resolution();
+        report.populated_cells = populated_cells;
+        report.outputs.push(OutputReport {
+            path: out_file.clone(),
+            grid_size: output_grid.resolution(),
+            populated_cells,
+        });
+        let progress = Progress::items(progress_mode, "writing", output_records.len() as u64);
+        match uncompressed_extension(out_file).as_deref() {
+            Some("tif" | "tiff") => {
+                // The raster is always the full grid, in the order of its rows
+                let mut dense_records =
+                    compute::fill_grid(output_records, *grid_size, &extent, GridOrder::RowMajor);
+                compute::set_cell_areas(&mut dense_records, output_grid);
+                let raster_files = geotiff::write_geotiff(
+                    &dense_records,
+                    out_file,
+                    clobber,
+                    &csv_format,
+                    &report.metadata(),
+                    *grid_size,
+                    &extent,
+                    &raster_format,
+                )?;
+                output_records = dense_records;
+                if raster_format.layout == RasterLayout::Files {
+                    report.outputs.pop();
+                    for path in raster_files {
+                        report.outputs.push(OutputReport {
+                            path,
+                            grid_size: *grid_size,
+                            populated_cells,
+                        });
+                    }
+                }
+            }
+            Some("png") => {
+                let mut dense_records =
+                    compute::fill_grid(output_records, *grid_size, &extent, GridOrder::RowMajor);
+                compute::set_cell_areas(&mut dense_records, output_grid);
+                render::write_png(
+                    &dense_records,
+                    out_file,
+                    clobber,
+                    &report.metadata(),
+                    *grid_size,
+                    &extent,
+                    &render_options,
+                )?;
+                output_records = dense_records;
+            }
+            Some("geojson" | "json") => geojson::write_geojson(
+                &output_records,
+                out_file,
+                clobber,
+                &csv_format,
+                &report.metadata(),
+                output_grid,
+                geometry,
+                &progress,
+            )?,
+            _ => write_csv::write_csv_using_serde(
+                &output_records,
+                out_file,
+                clobber,
+                &csv_format,
+                &report.metadata(),
+                &progress,
+            )?,
+        }
+        report.add_timing("writing", start);
+
+        if region_stats {
+            let start = Instant::now();
+            let regions = regions.as_ref().unwrap();
+            let summaries =
+                summaries::summarize_regions(&region_summaries, regions, &output_records);
+            let summaries_file = files::suffixed_path(out_file, "regions", Some("csv"));
+            write_csv::write_region_summaries(
+                &summaries,
+                &summaries_file,
+                clobber,
+                &csv_format,
+                &report.metadata(),
+            )?;
+            report.outputs.push(OutputReport {
+                path: summaries_file,
+                grid_size: output_grid.resolution(),
+                populated_cells,
+            });
+            report.add_timing("regions", start);
+        }
+
+        for table in &mean_tables {
+            let start = Instant::now();
+            let means = means::area_means(&output_records, *table, weighting);
+            let means_file = files::suffixed_path(out_file, table.name(), Some("csv"));
+            let mut metadata = report.metadata();
+            metadata.push(("means".to_string(), table.name().to_string()));
+            metadata.push((
+                "weights".to_string(),
+                args.get_one::<String>("weights").unwrap().clone(),
+            ));
+            write_csv::write_means(&means, *table, &means_file, clobber, &csv_format, &metadata)?;
+            report.outputs.push(OutputReport {
+                path: means_file,
+                grid_size: output_grid.resolution(),
+                populated_cells,
+            });
+            report.add_timing("means", start);
+        }
+    }
+
+    // The report describes the finest grid, with every output listed
+    report.grid.grid = output_grids[0].to_string();
+    report.grid.grid_size = output_grids[0].resolution();
+    report.populated_cells = report.outputs[0].populated_cells;
+
+    if let Some(report_file) = report_file {
+        report.write(report_file, clobber)?;
+    }
+
+    Ok(())
+}
+
+/// The format of the csv outputs, before any columns are selected.
+fn csv_format(args: &ArgMatches) -> CsvFormat {
+    let mut csv_format = CsvFormat {
+        scientific: args.get_flag("scientific"),
+        missing_value: args.get_one::<String>("fill_value").unwrap().clone(),
+        ..CsvFormat::default()
+    };
+    for (group, places) in args
+        .get_many::<(Option<String>, usize)>("precision")
+        .unwrap_or_default()
+    {
+        if group.as_deref() != Some("stats") {
+            csv_format.coordinate_precision = Some(*places);
+        }
+        if group.as_deref() != Some("coords") {
+            csv_format.statistic_precision = Some(*places);
+        }
+    }
+    csv_format
+}
+
+/// How the statistic of a PNG output is drawn.
+fn render_options(args: &ArgMatches) -> RenderOptions {
+    let statistic = args.get_one::<String>("statistic").unwrap();
+    RenderOptions {
+        column: RENDERED_STATISTICS
+            .iter()
+            .find(|(name, _)| name == statistic)
+            .unwrap()
+            .1,
+        colormap: Colormap::from_name(args.get_one::<String>("colormap").unwrap()).unwrap(),
+        range: args.get_one("range").copied(),
+        empty_color: *args.get_one("empty_color").unwrap(),
+        graticule: args.get_one("graticule").copied(),
+        colorbar: args.get_flag("colorbar"),
+    }
+}
+
+/// Compares the cells of two gridded outputs, writing the differences of each
+/// cell to the output and the summary metrics to a file named after it.
+fn compare_outputs(
+    args: &ArgMatches,
+    base_file: &Path,
+    new_file: &Path,
+    out_file: &PathBuf,
+    clobber: ClobberPolicy,
+) -> Result<(), Box<dyn Error>> {
+    let summary_file = files::suffixed_path(out_file, "summary", Some("csv"));
+    if is_stdio(out_file) {
+        Err("--compare can't be used when writing to stdout.")?
+    }
+    clobber.check(out_file)?;
+    clobber.check(&summary_file)?;
 
-    let grid_dict: HashMap<String, HeightData> =
-        compute::generate_histograms(&csv_records, grid_size)?;
+    let base = GriddedOutput::read(base_file)?;
+    let new = GriddedOutput::read(new_file)?;
+    let (base_grid, new_grid) = (base.grid_spec()?.grid, new.grid_spec()?.grid);
+    if base_grid != new_grid {
+        Err(format!(
+            "The outputs are on different grids, the {base_grid} and {new_grid} grids."
+        ))?
+    }
 
-    let output_records: Vec<WriteRecord> = compute::calc_stats(&grid_dict)?;
+    let tolerance: f64 = *args.get_one("tolerance").unwrap();
+    let cells = compare::compare_cells(&base.records, &new.records);
+    let summary = compare::summarize_comparison(&cells, tolerance);
+    if summary.compared_cells == 0 {
+        Err("The outputs have no populated cells in common, so are not on the same grid.")?
+    }
+    log::info!(
+        "Compared {} cells, with a bias of {:.3}, an RMSE of {:.3} and a correlation of {:.4}.",
+        summary.compared_cells,
+        summary.bias,
+        summary.rmse,
+        summary.correlation
+    );
+    log::info!(
+        "{:.2}% of the cells changed by more than {tolerance}, {} cells are only in the base output and {} only in the new.",
+        100.0 * summary.changed_fraction,
+        summary.base_only_cells,
+        summary.new_only_cells
+    );
 
-    write_csv::write_csv_using_serde(&output_records, out_file)?;
+    let metadata: Vec<(String, String)> = vec![
+        ("history".to_string(), format!("grid_test {TOOL_VERSION}")),
+        (
+            "created".to_string(),
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        ),
+        ("base".to_string(), base_file.display().to_string()),
+        ("new".to_string(), new_file.display().to_string()),
+        ("grid".to_string(), base_grid),
+    ];
+    let csv_format = csv_format(args);
+    write_csv::write_comparison(&cells, out_file, clobber, &csv_format, &metadata)?;
+    write_csv::write_comparison_summary(&summary, &summary_file, clobber, &csv_format, &metadata)?;
 
     Ok(())
 }
+
+/// Draws a statistic of a gridded output on the lon/lat grid to a PNG image.
+fn render_output(
+    args: &ArgMatches,
+    gridded_file: &Path,
+    out_file: &Path,
+    clobber: ClobberPolicy,
+) -> Result<(), Box<dyn Error>> {
+    if uncompressed_extension(out_file).as_deref() != Some("png") {
+        Err("--render draws a PNG image, so the output must end in .png.")?
+    }
+    clobber.check(out_file)?;
+
+    let gridded = GriddedOutput::read(gridded_file)?;
+    let spec = gridded.grid_spec()?;
+    if !spec.grid.starts_with("lon/lat") {
+        Err(format!(
+            "Only gridded outputs on the lon/lat grid can be rendered, not the {} grid.",
+            spec.grid
+        ))?
+    }
+    let extent = gridded.grid_extent()?;
+
+    let metadata: Vec<(String, String)> = vec![
+        ("history".to_string(), format!("grid_test {TOOL_VERSION}")),
+        (
+            "created".to_string(),
+            chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        ),
+        ("gridded".to_string(), gridded_file.display().to_string()),
+        ("grid".to_string(), spec.grid),
+    ];
+    let mut dense_records = compute::fill_grid(
+        gridded.records,
+        spec.grid_size,
+        &extent,
+        GridOrder::RowMajor,
+    );
+    compute::set_cell_areas(
+        &mut dense_records,
+        &Grid::LonLat {
+            grid_size: spec.grid_size,
+        },
+    );
+    render::write_png(
+        &dense_records,
+        out_file,
+        clobber,
+        &metadata,
+        spec.grid_size,
+        &extent,
+        &render_options(args),
+    )
+}
+
+/// Reads the gridded inputs, which must all be on the same lon/lat grid. Returns
+/// the grid size and the cells of all of the inputs, with a summary of each input
+/// counting the observations in its cells.
+fn read_gridded_inputs(
+    in_files: &[&PathBuf],
+    summaries: &mut Vec<FileReadSummary>,
+) -> Result<(f64, Vec<WriteRecord>), Box<dyn Error>> {
+    let mut input_grid_size: Option<f64> = None;
+    let mut cells: Vec<WriteRecord> = Vec::new();
+    for in_file in in_files {
+        let output = GriddedOutput::read(in_file)?;
+        let grid_size = output.lon_lat_grid_size()?;
+        match input_grid_size {
+            Some(input_grid_size) if input_grid_size != grid_size => Err(format!(
+                "The gridded inputs are on different grids, of {input_grid_size} and {grid_size} degrees."
+            ))?,
+            _ => input_grid_size = Some(grid_size),
+        }
+        summaries.push(FileReadSummary {
+            path: in_file.to_path_buf(),
+            num_records: output.records.iter().map(|r| r.counts as usize).sum(),
+            truncated: false,
+        });
+        cells.extend(output.records);
+    }
+    let input_grid_size = input_grid_size.ok_or("There are no gridded inputs")?;
+    log::info!("The gridded inputs are on the {input_grid_size} degree grid");
+    Ok((input_grid_size, cells))
+}
@@ -1,5 +1,10 @@
 // outputs.rs
 //
 // This file exposes the contents of the "outputs" module
+pub mod files; // src/outputs/files.rs
+pub mod geojson; // src/outputs/geojson.rs
+pub mod geotiff; // src/outputs/geotiff.rs
+pub mod render; // src/outputs/render.rs
+pub mod report; // src/outputs/report.rs
 pub mod writes; // src/outputs/writes.rs
 pub use writes::WriteRecord;
@@ -0,0 +1,356 @@
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use bzip2::write::BzEncoder;
+use flate2::write::GzEncoder;
+use xz2::write::XzEncoder;
+
+use crate::inputs::compression::{is_stdio, Compression};
+
+/// What to do when an output file already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClobberPolicy {
+    /// Replace the existing file.
+    Overwrite,
+    /// Refuse to run, leaving the existing file alone.
+    NoClobber,
+}
+
+impl ClobberPolicy {
+    /// Checks that the output may be written under the policy. This is done
+    /// before any work, so that a run isn't wasted on an output it can't write.
+    pub fn check(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if *self == ClobberPolicy::NoClobber && !is_stdio(path) && path.exists() {
+            Err(format!(
+                "The output file {path:?} already exists, use --overwrite to replace it."
+            ))?
+        }
+        Ok(())
+    }
+}
+
+/// The compressing writers, which each need finishing to write their trailer.
+enum Encoder {
+    None(Box<dyn Write>),
+    Gzip(GzEncoder<Box<dyn Write>>),
+    Bzip2(BzEncoder<Box<dyn Write>>),
+    Xz(XzEncoder<Box<dyn Write>>),
+    Zstd(zstd::Encoder<'static, Box<dyn Write>>),
+}
+
+/// The OutputFile struct is an output file (or stdout), compressed according to
+/// its extension. A file is written to a temporary file alongside it, which is
+/// only renamed to the final name by `commit`, so a failed run never leaves a
+/// truncated output behind.
+pub struct OutputFile {
+    encoder: Option<Encoder>,
+    /// The temporary file and its final path, unless writing to stdout.
+    temp: Option<(File, PathBuf, PathBuf)>,
+    /// The writer of a seekable output, with the uncompressed file it's staged in
+    /// when the output is compressed.
+    seekable: Option<(BufWriter<File>, Option<PathBuf>)>,
+    policy: ClobberPolicy,
+}
+
+impl OutputFile {
+    /// Creates the output, or writes to stdout if the path is "-".
+    pub fn create(path: &Path, policy: ClobberPolicy) -> Result<OutputFile, Box<dyn Error>> {
+        policy.check(path)?;
+
+        let (raw, temp): (Box<dyn Write>, _) = if is_stdio(path) {
+            (Box::new(BufWriter::new(io::stdout())), None)
+        } else {
+            let temp_path = temp_path(path);
+            let file = File::create(&temp_path)
+                .map_err(|e| format!("Could not create the output file {temp_path:?}: {e}"))?;
+            let raw = Box::new(BufWriter::new(file.try_clone()?));
+            (raw, Some((file, temp_path, path.to_path_buf())))
+        };
+
+        let compression = match is_stdio(path) {
+            true => Compression::None,
+            false => Compression::from_extension(path),
+        };
+        if compression != Compression::None {
+            log::debug!("\tCompressing {path:?} using {compression}");
+        }
+        let encoder = match compression {
+            Compression::None => Encoder::None(raw),
+            Compression::Gzip => Encoder::Gzip(GzEncoder::new(raw, flate2::Compression::default())),
+            Compression::Bzip2 => {
+                Encoder::Bzip2(BzEncoder::new(raw, bzip2::Compression::default()))
+            }
+            Compression::Xz => Encoder::Xz(XzEncoder::new(raw, 6)),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(raw, 0)?),
+        };
+
+        Ok(OutputFile {
+            encoder: Some(encoder),
+            temp,
+            seekable: None,
+            policy,
+        })
+    }
+
+    /// Creates an output which can be seeked, for the writers of formats which go
+    /// back to fill in offsets, such as GeoTIFF. A compressed output is staged in
+    /// another temporary file, and only compressed into place by `commit`.
+    pub fn create_seekable(
+        path: &Path,
+        policy: ClobberPolicy,
+    ) -> Result<OutputFile, Box<dyn Error>> {
+        if is_stdio(path) {
+            Err("This output can't be written to stdout.")?
+        }
+        let mut output = OutputFile::create(path, policy)?;
+
+        let (file, staged_path) = match Compression::from_extension(path) {
+            Compression::None => (output.temp.as_ref().unwrap().0.try_clone()?, None),
+            _ => {
+                let staged_path = temp_path(&temp_path(path));
+                let file = File::options()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&staged_path)
+                    .map_err(|e| {
+                        format!("Could not create the output file {staged_path:?}: {e}")
+                    })?;
+                (file, Some(staged_path))
+            }
+        };
+        output.seekable = Some((BufWriter::new(file), staged_path));
+        Ok(output)
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        if let Some((writer, _)) = &mut self.seekable {
+            return writer;
+        }
+        match self.encoder.as_mut().unwrap() {
+            Encoder::None(w) => w,
+            Encoder::Gzip(w) => w,
+            Encoder::Bzip2(w) => w,
+            Encoder::Xz(w) => w,
+            Encoder::Zstd(w) => w,
+        }
+    }
+
+    /// Finishes the compression, and moves the file into place. With --no-clobber
+    /// the file is linked into place, which unlike a rename fails if the output
+    /// was created by someone else since it was checked.
+    pub fn commit(mut self) -> Result<(), Box<dyn Error>> {
+        if let Some((writer, staged_path)) = self.seekable.take() {
+            let mut file = writer.into_inner().map_err(|e| e.to_string())?;
+            if let Some(staged_path) = staged_path {
+                file.seek(SeekFrom::Start(0))?;
+                let copied = io::copy(&mut file, self.writer());
+                let _ = fs::remove_file(&staged_path);
+                copied?;
+            }
+        }
+        let mut raw = match self.encoder.take().unwrap() {
+            Encoder::None(w) => w,
+            Encoder::Gzip(w) => w.finish()?,
+            Encoder::Bzip2(w) => w.finish()?,
+            Encoder::Xz(w) => w.finish()?,
+            Encoder::Zstd(w) => w.finish()?,
+        };
+        raw.flush()?;
+        drop(raw);
+
+        if let Some((file, temp_path, path)) = self.temp.take() {
+            file.sync_all()?;
+            match self.policy {
+                ClobberPolicy::Overwrite => fs::rename(&temp_path, &path)
+                    .map_err(|e| format!("Could not move {temp_path:?} to {path:?}: {e}"))?,
+                ClobberPolicy::NoClobber => {
+                    let linked = fs::hard_link(&temp_path, &path);
+                    fs::remove_file(&temp_path)?;
+                    linked.map_err(|e| match e.kind() {
+                        io::ErrorKind::AlreadyExists => format!(
+                            "The output file {path:?} already exists, use --overwrite to replace it."
+                        ),
+                        _ => format!("Could not move {temp_path:?} to {path:?}: {e}"),
+                    })?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for OutputFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+impl Seek for OutputFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.seekable {
+            Some((writer, _)) => writer.seek(pos),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The output wasn't created to be seekable",
+            )),
+        }
+    }
+}
+
+impl Drop for OutputFile {
+    /// Removes the temporary files of an output which was never committed.
+    fn drop(&mut self) {
+        if let Some((_, temp_path, _)) = &self.temp {
+            let _ = fs::remove_file(temp_path);
+        }
+        if let Some((_, Some(staged_path))) = &self.seekable {
+            let _ = fs::remove_file(staged_path);
+        }
+    }
+}
+
+/// The output path with the suffix added to the file name, ahead of its extension
+/// and any compression extension, so "out.csv.gz" becomes "out_SUFFIX.csv.gz". The
+/// extension is replaced if given, such as for a csv table alongside a GeoJSON output.
+pub fn suffixed_path(path: &Path, suffix: &str, extension: Option<&str>) -> PathBuf {
+    let compression = Compression::from_extension(path);
+    let uncompressed = match compression {
+        Compression::None => path.to_path_buf(),
+        _ => path.with_extension(""),
+    };
+    let stem = uncompressed
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let extension = extension.map(String::from).or(uncompressed
+        .extension()
+        .map(|e| e.to_string_lossy().to_string()));
+    let extensions: String = [extension.as_deref(), compression.extension()]
+        .into_iter()
+        .flatten()
+        .map(|extension| format!(".{extension}"))
+        .collect();
+    path.with_file_name(format!("{stem}_{suffix}{extensions}"))
+}
+
+/// The hidden temporary file, in the same directory as the output so that
+/// the rename doesn't cross filesystems.
+fn temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{file_name}.{}.tmp", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Seek, SeekFrom, Write},
+        path::Path,
+    };
+
+    use crate::{
+        inputs::compression::Input,
+        outputs::files::{suffixed_path, temp_path, ClobberPolicy, OutputFile},
+    };
+
+    #[test]
+    /// The output only appears once committed, and is compressed by extension.
+    fn atomic_compressed_output() {
+        let path =
+            std::env::temp_dir().join(format!("grid_test_output_{}.csv.gz", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut output = OutputFile::create(&path, ClobberPolicy::Overwrite).unwrap();
+        output.write_all(b"a,b\n1,2\n").unwrap();
+        assert!(!path.exists());
+        assert!(temp_path(&path).exists());
+        output.commit().unwrap();
+        assert!(!temp_path(&path).exists());
+
+        let mut contents = String::new();
+        Input::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "a,b\n1,2\n");
+
+        // An existing output is left alone with --no-clobber
+        assert!(OutputFile::create(&path, ClobberPolicy::NoClobber).is_err());
+
+        // Nor is an output created after the check replaced
+        fs::remove_file(&path).unwrap();
+        let mut output = OutputFile::create(&path, ClobberPolicy::NoClobber).unwrap();
+        output.write_all(b"a,b\n3,4\n").unwrap();
+        fs::write(&path, "existing").unwrap();
+        assert!(output.commit().is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "existing");
+        assert!(!temp_path(&path).exists());
+
+        // An abandoned output leaves no trace
+        let output = OutputFile::create(&path, ClobberPolicy::Overwrite).unwrap();
+        drop(output);
+        assert!(!temp_path(&path).exists());
+        assert!(path.exists());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    /// A seekable output is rewritten in place, and compressed once committed.
+    fn seekable_output() {
+        for extension in ["bin", "bin.zst"] {
+            let path = std::env::temp_dir().join(format!(
+                "grid_test_seekable_{}.{extension}",
+                std::process::id()
+            ));
+            let _ = fs::remove_file(&path);
+
+            let mut output = OutputFile::create_seekable(&path, ClobberPolicy::Overwrite).unwrap();
+            output.write_all(b"xxxx5678").unwrap();
+            output.seek(SeekFrom::Start(0)).unwrap();
+            output.write_all(b"1234").unwrap();
+            output.commit().unwrap();
+
+            let mut contents = String::new();
+            Input::open(&path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, "12345678");
+            assert!(!temp_path(&temp_path(&path)).exists());
+            fs::remove_file(path).unwrap();
+        }
+        assert!(OutputFile::create_seekable(Path::new("-"), ClobberPolicy::Overwrite).is_err());
+    }
+
+    #[test]
+    /// The suffix goes ahead of the extensions.
+    fn suffixed_paths() {
+        assert_eq!(
+            suffixed_path(Path::new("dir/out.csv.gz"), "0.25deg", None),
+            Path::new("dir/out_0.25deg.csv.gz")
+        );
+        assert_eq!(
+            suffixed_path(Path::new("out.csv"), "1deg", None),
+            Path::new("out_1deg.csv")
+        );
+        assert_eq!(
+            suffixed_path(Path::new("out"), "1deg", None),
+            Path::new("out_1deg")
+        );
+        assert_eq!(
+            suffixed_path(Path::new("out.geojson.gz"), "zonal", Some("csv")),
+            Path::new("out_zonal.csv.gz")
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::{error::Error, io::Write, path::PathBuf};
+
+use serde_json::{json, Value};
+
+use crate::compute::grids::Grid;
+use crate::outputs::files::{ClobberPolicy, OutputFile};
+use crate::outputs::writes::CsvFormat;
+use crate::outputs::WriteRecord;
+use crate::progress::Progress;
+
+/// The geometry of the features written for each grid cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Geometry {
+    /// The outline of the cell.
+    Polygons,
+    /// The center of the cell.
+    Points,
+}
+
+impl Geometry {
+    pub fn from_name(name: &str) -> Option<Geometry> {
+        match name {
+            "polygons" => Some(Geometry::Polygons),
+            "points" => Some(Geometry::Points),
+            _ => None,
+        }
+    }
+}
+
+/// This function splits a ring crossing the antimeridian into the rings on
+/// either side of it, as RFC 7946 asks. A ring is taken to cross it when its
+/// longitudes span more than half the globe.
+fn split_antimeridian(ring: &[(f64, f64)]) -> Vec<Vec<(f64, f64)>> {
+    let (west, east) = ring
+        .iter()
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(w, e), p| {
+            (w.min(p.0), e.max(p.0))
+        });
+    if east - west <= 180.0 {
+        return vec![ring.to_vec()];
+    }
+
+    // Unwrap the ring east of the antimeridian, then clip it on either side
+    let unwrapped: Vec<(f64, f64)> = ring
+        .iter()
+        .map(|(lon, lat)| (if *lon < 0.0 { lon + 360.0 } else { *lon }, *lat))
+        .collect();
+    let clip = |keep_west: bool| -> Vec<(f64, f64)> {
+        let inside = |lon: f64| (lon <= 180.0) == keep_west;
+        let mut clipped: Vec<(f64, f64)> = Vec::new();
+        for (i, point) in unwrapped.iter().enumerate() {
+            let previous = unwrapped[(i + unwrapped.len() - 1) % unwrapped.len()];
+            if inside(point.0) != inside(previous.0) {
+                let t = (180.0 - previous.0) / (point.0 - previous.0);
+                clipped.push((180.0, previous.1 + t * (point.1 - previous.1)));
+            }
+            if inside(point.0) {
+                clipped.push(*point);
+            }
+        }
+        if !keep_west {
+            for point in &mut clipped {
+                point.0 -= 360.0;
+            }
+        }
+        clipped
+    };
+    [clip(true), clip(false)]
+        .into_iter()
+        .filter(|ring| ring.len() >= 3)
+        .collect()
+}
+
+/// The GeoJSON geometry of the outline of the cell holding the point, as a
+/// polygon, or as a multipolygon when split by the antimeridian. The rings are
+/// closed and anticlockwise. Cells reaching off the projection have no geometry.
+fn cell_polygon(grid: &Grid, format: &CsvFormat, longitude: f64, latitude: f64) -> Value {
+    let Some((column, row)) = grid.cell_index(longitude, latitude) else {
+        return Value::Null;
+    };
+    let corners = grid.cell_corners(column, row);
+    if corners
+        .iter()
+        .any(|(lon, lat)| lon.is_nan() || lat.is_nan())
+    {
+        return Value::Null;
+    }
+
+    let polygons: Vec<Value> = split_antimeridian(&corners)
+        .into_iter()
+        .map(|mut ring| {
+            // The shoelace formula gives a negative area for a clockwise ring
+            let area: f64 = (0..ring.len())
+                .map(|i| {
+                    let ((x_0, y_0), (x_1, y_1)) = (ring[i], ring[(i + 1) % ring.len()]);
+                    x_0 * y_1 - x_1 * y_0
+                })
+                .sum();
+            if area < 0.0 {
+                ring.reverse();
+            }
+            ring.push(ring[0]);
+            let ring: Vec<[f64; 2]> = ring
+                .iter()
+                .map(|(lon, lat)| [format.round_coordinate(*lon), format.round_coordinate(*lat)])
+                .collect();
+            json!([ring])
+        })
+        .collect();
+    match &polygons[..] {
+        [polygon] => json!({"type": "Polygon", "coordinates": polygon}),
+        _ => json!({"type": "MultiPolygon", "coordinates": polygons}),
+    }
+}
+
+/// This function writes the grid cells as a GeoJSON feature collection, with the
+/// selected columns as the properties of each feature and the metadata as a
+/// foreign member of the collection. Each feature is written to its own line.
+#[allow(clippy::too_many_arguments)]
+pub fn write_geojson(
+    csv_records: &[WriteRecord],
+    out_file: &PathBuf,
+    clobber: ClobberPolicy,
+    format: &CsvFormat,
+    metadata: &[(String, String)],
+    grid: &Grid,
+    geometry: Geometry,
+    progress: &Progress,
+) -> Result<(), Box<dyn Error>> {
+    log::info!("Writing the grid cells as GeoJSON to file {out_file:?}...");
+
+    let mut file = OutputFile::create(out_file, clobber)?;
+    let metadata: serde_json::Map<String, Value> = metadata
+        .iter()
+        .map(|(key, value)| (key.clone(), json!(value)))
+        .collect();
+    writeln!(file, "{{\"type\": \"FeatureCollection\",")?;
+    writeln!(file, "\"metadata\": {},", Value::Object(metadata))?;
+    writeln!(file, "\"features\": [")?;
+
+    for (i, record) in csv_records.iter().enumerate() {
+        let geometry = match geometry {
+            Geometry::Polygons => cell_polygon(grid, format, record.longitude, record.latitude),
+            Geometry::Points => json!({
+                "type": "Point",
+                "coordinates": [
+                    format.round_coordinate(record.longitude),
+                    format.round_coordinate(record.latitude)
+                ]
+            }),
+        };
+        let feature = json!({
+            "type": "Feature",
+            "geometry": geometry,
+            "properties": format.json_fields(record),
+        });
+        let separator = if i + 1 < csv_records.len() { "," } else { "" };
+        writeln!(file, "{feature}{separator}")?;
+        if (i + 1) % 1000 == 0 {
+            progress.update(i as u64 + 1, 0);
+        }
+    }
+    progress.finish();
+
+    writeln!(file, "]}}")?;
+    file.commit()?;
+
+    log::info!(
+        "There are {} features in the GeoJSON file.",
+        csv_records.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::compute::grids::Grid;
+    use crate::outputs::geojson::{cell_polygon, split_antimeridian};
+    use crate::outputs::writes::CsvFormat;
+
+    #[test]
+    /// The cells are closed anticlockwise polygons, split at the antimeridian.
+    fn cell_polygons() {
+        let format = CsvFormat::default();
+        let polygon = cell_polygon(&Grid::LonLat { grid_size: 0.5 }, &format, 10.25, -0.25);
+        assert_eq!(
+            polygon,
+            json!({"type": "Polygon", "coordinates": [[
+                [10.0, -0.5], [10.5, -0.5], [10.5, 0.0], [10.0, 0.0], [10.0, -0.5]
+            ]]})
+        );
+
+        let rings = split_antimeridian(&[(179.0, 0.0), (-179.0, 0.0), (-179.0, 1.0), (179.0, 1.0)]);
+        assert_eq!(
+            rings,
+            [
+                vec![(179.0, 0.0), (180.0, 0.0), (180.0, 1.0), (179.0, 1.0)],
+                vec![(-180.0, 0.0), (-179.0, 0.0), (-179.0, 1.0), (-180.0, 1.0)]
+            ]
+        );
+
+        // A polar stereographic cell spanning the antimeridian becomes a multipolygon
+        let grid = Grid::PolarStereographic {
+            resolution: 100.0,
+            true_scale_lat: 70.0,
+            central_lon: -45.0,
+        };
+        let polygon = cell_polygon(&grid, &format, 179.9, 80.0);
+        assert_eq!(polygon["type"], "MultiPolygon");
+    }
+}
@@ -0,0 +1,387 @@
+use std::{
+    error::Error,
+    marker::PhantomData,
+    path::{Path, PathBuf},
+};
+
+use tiff::{
+    encoder::{colortype::ColorType, TiffEncoder, TiffValue},
+    tags::{PhotometricInterpretation, SampleFormat, Tag},
+};
+
+use crate::compute;
+use crate::inputs::sensors::GridExtent;
+use crate::outputs::files::{self, ClobberPolicy, OutputFile};
+use crate::outputs::writes::{CsvFormat, COLUMNS};
+use crate::outputs::WriteRecord;
+
+/// The TIFF tag holding the GDAL metadata, including the band descriptions.
+const GDAL_METADATA: u16 = 42112;
+
+/// The GeoTIFF keys of an EPSG:4326 lon/lat grid, with each pixel covering
+/// the area of its cell: the key directory header, then the model type
+/// (geographic), the raster type (pixel is area) and the geographic CRS.
+const EPSG_4326_KEYS: [u16; 16] = [
+    1, 1, 0, 3, //
+    1024, 0, 1, 2, //
+    1025, 0, 1, 1, //
+    2048, 0, 1, 4326,
+];
+
+/// The type of the samples of the raster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleType {
+    Float32,
+    Float64,
+}
+
+impl SampleType {
+    pub fn from_name(name: &str) -> Option<SampleType> {
+        match name {
+            "float32" => Some(SampleType::Float32),
+            "float64" => Some(SampleType::Float64),
+            _ => None,
+        }
+    }
+}
+
+/// How the statistics are laid out in the rasters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RasterLayout {
+    /// One file, with a band per statistic.
+    Bands,
+    /// One file per statistic, named after the statistic.
+    Files,
+}
+
+impl RasterLayout {
+    pub fn from_name(name: &str) -> Option<RasterLayout> {
+        match name {
+            "bands" => Some(RasterLayout::Bands),
+            "files" => Some(RasterLayout::Files),
+            _ => None,
+        }
+    }
+}
+
+/// The RasterFormat struct holds how the GeoTIFF outputs are written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RasterFormat {
+    pub sample_type: SampleType,
+    pub layout: RasterLayout,
+    /// Written in place of NaN, such as the mean and standard deviation of empty cells.
+    pub nodata: f64,
+}
+
+/// The color type of a raster of N floating point bands.
+struct Bands<T, const N: usize>(PhantomData<T>);
+
+impl<const N: usize> ColorType for Bands<f32, N> {
+    type Inner = f32;
+    const TIFF_VALUE: PhotometricInterpretation = PhotometricInterpretation::BlackIsZero;
+    const BITS_PER_SAMPLE: &'static [u16] = &[32; N];
+    const SAMPLE_FORMAT: &'static [SampleFormat] = &[SampleFormat::IEEEFP; N];
+}
+
+impl<const N: usize> ColorType for Bands<f64, N> {
+    type Inner = f64;
+    const TIFF_VALUE: PhotometricInterpretation = PhotometricInterpretation::BlackIsZero;
+    const BITS_PER_SAMPLE: &'static [u16] = &[64; N];
+    const SAMPLE_FORMAT: &'static [SampleFormat] = &[SampleFormat::IEEEFP; N];
+}
+
+/// The georeferencing and descriptive tags of a raster.
+struct RasterTags {
+    /// The (west, north) corner of the raster, and the size of its pixels.
+    origin: (f64, f64),
+    grid_size: f64,
+    nodata: String,
+    gdal_metadata: String,
+}
+
+/// Escapes the text for the GDAL metadata XML.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Encodes the interleaved samples as a GeoTIFF, written to the output a strip
+/// of rows at a time.
+fn encode<C: ColorType>(
+    file: &mut OutputFile,
+    samples: impl Iterator<Item = C::Inner>,
+    width: u32,
+    height: u32,
+    tags: &RasterTags,
+) -> Result<(), Box<dyn Error>>
+where
+    [C::Inner]: TiffValue,
+{
+    let mut tiff = TiffEncoder::new(file)?;
+    let mut image = tiff.new_image::<C>(width, height)?;
+
+    let encoder = image.encoder();
+    let bands = C::BITS_PER_SAMPLE.len();
+    if bands > 1 {
+        encoder.write_tag(Tag::ExtraSamples, &vec![0_u16; bands - 1][..])?;
+    }
+    let (west, north) = tags.origin;
+    encoder.write_tag(
+        Tag::ModelPixelScaleTag,
+        &[tags.grid_size, tags.grid_size, 0.0][..],
+    )?;
+    encoder.write_tag(
+        Tag::ModelTiepointTag,
+        &[0.0, 0.0, 0.0, west, north, 0.0][..],
+    )?;
+    encoder.write_tag(Tag::GeoKeyDirectoryTag, &EPSG_4326_KEYS[..])?;
+    encoder.write_tag(Tag::GdalNodata, &tags.nodata[..])?;
+    encoder.write_tag(Tag::Unknown(GDAL_METADATA), &tags.gdal_metadata[..])?;
+
+    let mut samples = samples.peekable();
+    while samples.peek().is_some() {
+        let strip_samples = image.next_strip_sample_count() as usize;
+        let strip: Vec<C::Inner> = samples.by_ref().take(strip_samples).collect();
+        image.write_strip(&strip)?;
+    }
+    image.finish()?;
+    Ok(())
+}
+
+/// Encodes the band-interleaved values as samples of the type, for the number of bands.
+fn encode_bands(
+    file: &mut OutputFile,
+    values: impl Iterator<Item = f64>,
+    bands: usize,
+    sample_type: SampleType,
+    width: u32,
+    height: u32,
+    tags: &RasterTags,
+) -> Result<(), Box<dyn Error>> {
+    match sample_type {
+        SampleType::Float32 => {
+            let samples = values.map(|value| value as f32);
+            match bands {
+                1 => encode::<Bands<f32, 1>>(file, samples, width, height, tags),
+                2 => encode::<Bands<f32, 2>>(file, samples, width, height, tags),
+                3 => encode::<Bands<f32, 3>>(file, samples, width, height, tags),
+                4 => encode::<Bands<f32, 4>>(file, samples, width, height, tags),
+                5 => encode::<Bands<f32, 5>>(file, samples, width, height, tags),
+                6 => encode::<Bands<f32, 6>>(file, samples, width, height, tags),
+                _ => Err(format!("A raster can't have {bands} bands"))?,
+            }
+        }
+        SampleType::Float64 => match bands {
+            1 => encode::<Bands<f64, 1>>(file, values, width, height, tags),
+            2 => encode::<Bands<f64, 2>>(file, values, width, height, tags),
+            3 => encode::<Bands<f64, 3>>(file, values, width, height, tags),
+            4 => encode::<Bands<f64, 4>>(file, values, width, height, tags),
+            5 => encode::<Bands<f64, 5>>(file, values, width, height, tags),
+            6 => encode::<Bands<f64, 6>>(file, values, width, height, tags),
+            _ => Err(format!("A raster can't have {bands} bands"))?,
+        },
+    }
+}
+
+/// The files of a raster output, each with the statistics columns written to it
+/// as bands. There are none when no statistics columns are selected.
+fn band_groups<'a>(
+    out_file: &Path,
+    format: &'a CsvFormat,
+    raster: &RasterFormat,
+) -> Vec<(PathBuf, Vec<&'a (usize, String)>)> {
+    let bands: Vec<&(usize, String)> = format
+        .columns
+        .iter()
+        .filter(|(index, _)| COLUMNS[*index] != "Longitude" && COLUMNS[*index] != "Latitude")
+        .collect();
+    match raster.layout {
+        _ if bands.is_empty() => Vec::new(),
+        RasterLayout::Bands => vec![(out_file.to_path_buf(), bands)],
+        RasterLayout::Files => bands
+            .into_iter()
+            .map(|band| (files::suffixed_path(out_file, &band.1, None), vec![band]))
+            .collect(),
+    }
+}
+
+/// The files written for a raster output, which are named after the statistics
+/// when each is written to its own file.
+pub fn raster_files(out_file: &Path, format: &CsvFormat, raster: &RasterFormat) -> Vec<PathBuf> {
+    band_groups(out_file, format, raster)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// This function writes the selected statistics of a dense lon/lat grid, in
+/// row-major order over the extent as from `fill_grid`, as GeoTIFF rasters
+/// georeferenced to EPSG:4326. The metadata and the names of the bands are
+/// written as GDAL metadata. The coordinate columns are left out, being given
+/// by the georeferencing.
+#[allow(clippy::too_many_arguments)]
+pub fn write_geotiff(
+    dense_records: &[WriteRecord],
+    out_file: &Path,
+    clobber: ClobberPolicy,
+    format: &CsvFormat,
+    metadata: &[(String, String)],
+    grid_size: f64,
+    extent: &GridExtent,
+    raster: &RasterFormat,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let (width, height) = compute::grid_dimensions(grid_size, extent);
+    if dense_records.len() != width * height {
+        Err(format!(
+            "The grid has {} cells rather than the {width} by {height} of the raster",
+            dense_records.len()
+        ))?
+    }
+    let band_groups = band_groups(out_file, format, raster);
+    if band_groups.is_empty() {
+        Err("There are no statistics columns to write to the raster")?
+    }
+
+    let origin = (
+        grid_size * (extent.west / grid_size).floor(),
+        grid_size * (extent.north / grid_size).ceil(),
+    );
+    let nodata = match raster.nodata.is_nan() {
+        true => "nan".to_string(),
+        false => raster.nodata.to_string(),
+    };
+    let mut out_files: Vec<PathBuf> = Vec::new();
+    for (path, bands) in band_groups {
+        log::info!("Writing the {width} by {height} raster to file {path:?}...");
+
+        let mut gdal_metadata = String::from("<GDALMetadata>");
+        for (key, value) in metadata {
+            gdal_metadata += &format!(
+                "<Item name=\"{}\">{}</Item>",
+                escape_xml(key),
+                escape_xml(value)
+            );
+        }
+        for (band, (_, name)) in bands.iter().enumerate() {
+            gdal_metadata += &format!(
+                "<Item name=\"DESCRIPTION\" sample=\"{band}\" role=\"description\">{}</Item>",
+                escape_xml(name)
+            );
+        }
+        gdal_metadata += "</GDALMetadata>";
+        let tags = RasterTags {
+            origin,
+            grid_size,
+            nodata: nodata.clone(),
+            gdal_metadata,
+        };
+
+        let values = dense_records
+            .iter()
+            .flat_map(|record| bands.iter().map(|(index, _)| record.column_value(*index)))
+            .map(|value| if value.is_nan() { raster.nodata } else { value });
+        let mut file = OutputFile::create_seekable(&path, clobber)?;
+        encode_bands(
+            &mut file,
+            values,
+            bands.len(),
+            raster.sample_type,
+            width as u32,
+            height as u32,
+            &tags,
+        )?;
+        file.commit()?;
+        out_files.push(path);
+    }
+
+    Ok(out_files)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tiff::decoder::Decoder;
+    use tiff::tags::Tag;
+
+    use crate::compute::{calc_stats, fill_grid, generate_histograms, GridOrder};
+    use crate::inputs::{sensors::GridExtent, ReadRecord};
+    use crate::outputs::files::ClobberPolicy;
+    use crate::outputs::geotiff::{write_geotiff, RasterFormat, RasterLayout, SampleType};
+    use crate::outputs::writes::CsvFormat;
+
+    #[test]
+    /// The raster holds a band per statistic over the extent, georeferenced at its corner.
+    fn geotiff_bands() {
+        let records = vec![ReadRecord {
+            longitude: 1.2,
+            latitude: -0.5,
+            height: 1000,
+            footprint: None,
+        }];
+        let extent = GridExtent {
+            west: 0.0,
+            east: 3.0,
+            south: -1.0,
+            north: 1.0,
+        };
+        let grid_dict = generate_histograms(&records, &1.0).unwrap();
+        let dense = fill_grid(
+            calc_stats(&grid_dict).unwrap(),
+            1.0,
+            &extent,
+            GridOrder::RowMajor,
+        );
+
+        let mut format = CsvFormat::default();
+        format
+            .select_columns(&["Counts".to_string(), "MeanHeight".to_string()])
+            .unwrap();
+        let raster = RasterFormat {
+            sample_type: SampleType::Float32,
+            layout: RasterLayout::Bands,
+            nodata: -999.0,
+        };
+        let path =
+            std::env::temp_dir().join(format!("grid_test_raster_{}.tif", std::process::id()));
+        let out_files = write_geotiff(
+            &dense,
+            &path,
+            ClobberPolicy::Overwrite,
+            &format,
+            &[],
+            1.0,
+            &extent,
+            &raster,
+        )
+        .unwrap();
+        assert_eq!(out_files, std::slice::from_ref(&path));
+
+        let mut decoder = Decoder::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (3, 2));
+        assert_eq!(
+            decoder.get_tag_f64_vec(Tag::ModelTiepointTag).unwrap(),
+            [0.0, 0.0, 0.0, 0.0, 1.0, 0.0]
+        );
+
+        // The decoder doesn't read multi-band grayscale, so read the strip directly
+        let offset = decoder.get_tag_u64_vec(Tag::StripOffsets).unwrap()[0] as usize;
+        let length = decoder.get_tag_u64_vec(Tag::StripByteCounts).unwrap()[0] as usize;
+        let bytes = fs::read(&path).unwrap();
+        let samples: Vec<f32> = bytes[offset..offset + length]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        // The populated cell is in the middle of the southern row
+        assert_eq!(
+            samples,
+            [
+                0.0, -999.0, 0.0, -999.0, 0.0, -999.0, //
+                0.0, -999.0, 1.0, 1000.0, 0.0, -999.0
+            ]
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
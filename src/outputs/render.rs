@@ -0,0 +1,460 @@
+use std::{error::Error, io::Write, ops::Range, path::Path};
+
+use crate::compute;
+use crate::inputs::sensors::GridExtent;
+use crate::outputs::files::{ClobberPolicy, OutputFile};
+use crate::outputs::writes::COLUMNS;
+use crate::outputs::WriteRecord;
+
+/// The width of the image below which the cells are drawn larger than a pixel.
+const MIN_MAP_WIDTH: usize = 720;
+
+/// The width of the image above which several cells are drawn to a pixel.
+const MAX_MAP_WIDTH: usize = 3600;
+
+/// The smallest width of an image with a colorbar, so its labels fit.
+const MIN_COLORBAR_WIDTH: usize = 256;
+
+/// The height of the colorbar, and the margins around it, in pixels.
+const COLORBAR_HEIGHT: usize = 12;
+const MARGIN: usize = 8;
+
+/// The color of the graticule and of the colorbar labels.
+const LINE_COLOR: [u8; 3] = [48, 48, 48];
+
+/// The statistics which may be rendered, and their columns of COLUMNS.
+pub const RENDERED_STATISTICS: [(&str, usize); 6] = [
+    ("count", 2),
+    ("sum", 3),
+    ("sum-squared", 4),
+    ("mean", 5),
+    ("stdev", 6),
+    ("area", 7),
+];
+
+/// The colormaps, as colors evenly spaced from the low to the high end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Colormap {
+    Viridis,
+    Plasma,
+    Gray,
+    /// A diverging map from blue through gray to red, for differences.
+    CoolWarm,
+}
+
+impl Colormap {
+    pub fn from_name(name: &str) -> Option<Colormap> {
+        match name {
+            "viridis" => Some(Colormap::Viridis),
+            "plasma" => Some(Colormap::Plasma),
+            "gray" => Some(Colormap::Gray),
+            "coolwarm" => Some(Colormap::CoolWarm),
+            _ => None,
+        }
+    }
+
+    fn anchors(&self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Viridis => &[
+                [68, 1, 84],
+                [71, 44, 122],
+                [59, 81, 139],
+                [44, 113, 142],
+                [33, 144, 141],
+                [39, 173, 129],
+                [92, 200, 99],
+                [170, 220, 50],
+                [253, 231, 37],
+            ],
+            Colormap::Plasma => &[
+                [13, 8, 135],
+                [84, 2, 163],
+                [139, 10, 165],
+                [185, 50, 137],
+                [219, 92, 104],
+                [244, 136, 73],
+                [254, 188, 43],
+                [240, 249, 33],
+            ],
+            Colormap::Gray => &[[0, 0, 0], [255, 255, 255]],
+            Colormap::CoolWarm => &[[59, 76, 192], [221, 221, 221], [180, 4, 38]],
+        }
+    }
+
+    /// The color at the fraction of the way along the colormap, clamped to its ends.
+    pub fn color(&self, fraction: f64) -> [u8; 3] {
+        let anchors = self.anchors();
+        let position = fraction.clamp(0.0, 1.0) * (anchors.len() - 1) as f64;
+        let index = (position.floor() as usize).min(anchors.len() - 2);
+        let t = position - index as f64;
+        let (low, high) = (anchors[index], anchors[index + 1]);
+        [0, 1, 2].map(|i| (low[i] as f64 + t * (high[i] as f64 - low[i] as f64)).round() as u8)
+    }
+}
+
+/// The RenderOptions struct holds how a statistic is drawn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    /// The column of COLUMNS drawn.
+    pub column: usize,
+    pub colormap: Colormap,
+    /// The values at the ends of the colormap, or the range of the values.
+    pub range: Option<(f64, f64)>,
+    /// The color of the cells without a value.
+    pub empty_color: [u8; 3],
+    /// The spacing of the lines of longitude and latitude, in degrees.
+    pub graticule: Option<f64>,
+    pub colorbar: bool,
+}
+
+/// Parses a color given as RRGGBB hex digits, with or without a leading '#'.
+pub fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let digits = color.trim_start_matches('#');
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("{color:?} isn't a RRGGBB color"));
+    }
+    let channel = |i: usize| u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).unwrap();
+    Ok([channel(0), channel(1), channel(2)])
+}
+
+/// The 5x7 pixel glyphs of the characters of the colorbar labels, a row to a byte.
+fn glyph(c: char) -> [u8; 7] {
+    match c {
+        '0' => [14, 17, 19, 21, 25, 17, 14],
+        '1' => [4, 12, 4, 4, 4, 4, 14],
+        '2' => [14, 17, 1, 2, 4, 8, 31],
+        '3' => [31, 2, 4, 2, 1, 17, 14],
+        '4' => [2, 6, 10, 18, 31, 2, 2],
+        '5' => [31, 16, 30, 1, 1, 17, 14],
+        '6' => [6, 8, 16, 30, 17, 17, 14],
+        '7' => [31, 1, 2, 4, 8, 8, 8],
+        '8' => [14, 17, 17, 14, 17, 17, 14],
+        '9' => [14, 17, 17, 15, 1, 2, 12],
+        '-' => [0, 0, 0, 31, 0, 0, 0],
+        '+' => [0, 4, 4, 31, 4, 4, 0],
+        '.' => [0, 0, 0, 0, 0, 12, 12],
+        'e' => [0, 0, 14, 17, 31, 16, 14],
+        _ => [0; 7],
+    }
+}
+
+/// The label of a value of the colorbar, short enough to fit below it.
+fn format_label(value: f64) -> String {
+    if value != 0.0 && (value.abs() >= 1.0e5 || value.abs() < 1.0e-2) {
+        return format!("{value:.2e}");
+    }
+    let label = format!("{value:.2}");
+    label
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// The Canvas struct is an RGB image being drawn.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize, background: [u8; 3]) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: background.repeat(width * height),
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let offset = 3 * (y * self.width + x);
+            self.pixels[offset..offset + 3].copy_from_slice(&color);
+        }
+    }
+
+    /// Mixes the color half and half with the pixel, so the map shows through.
+    fn blend(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        if x < self.width && y < self.height {
+            let offset = 3 * (y * self.width + x);
+            for (pixel, channel) in self.pixels[offset..offset + 3].iter_mut().zip(color) {
+                *pixel = ((*pixel as u16 + channel as u16) / 2) as u8;
+            }
+        }
+    }
+
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 3]) {
+        for row in y..y + height {
+            for column in x..x + width {
+                self.set(column, row, color);
+            }
+        }
+    }
+
+    /// Draws the text with its top left corner at the point.
+    fn text(&mut self, x: usize, y: usize, text: &str) {
+        for (i, c) in text.chars().enumerate() {
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for column in 0..5 {
+                    if bits & (16 >> column) != 0 {
+                        self.set(x + 6 * i + column, y + row, LINE_COLOR);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The cells of a grid drawn in a pixel of an image with `step` cells to `scale`
+/// pixels, out of the `cells` of a row or column of the grid.
+fn pixel_cells(pixel: usize, step: usize, scale: usize, cells: usize) -> Range<usize> {
+    (pixel * step / scale).min(cells)..((pixel + 1) * step).div_ceil(scale).min(cells)
+}
+
+/// This function draws a statistic of a dense lon/lat grid, in row-major order
+/// over the extent as from `fill_grid`, to a PNG image with the metadata as text
+/// chunks. Each cell is drawn as a square of pixels, larger than one pixel for
+/// small grids, while the grids wider than MAX_MAP_WIDTH are drawn with each pixel
+/// the mean of the cells it covers. Cells without observations, or without a
+/// value of the statistic, are drawn in the empty color. The image is written a
+/// row of pixels at a time.
+#[allow(clippy::too_many_arguments)]
+pub fn write_png(
+    dense_records: &[WriteRecord],
+    out_file: &Path,
+    clobber: ClobberPolicy,
+    metadata: &[(String, String)],
+    grid_size: f64,
+    extent: &GridExtent,
+    options: &RenderOptions,
+) -> Result<(), Box<dyn Error>> {
+    let (columns, rows) = compute::grid_dimensions(grid_size, extent);
+    if dense_records.len() != columns * rows {
+        Err(format!(
+            "The grid has {} cells rather than the {columns} by {rows} of the image",
+            dense_records.len()
+        ))?
+    }
+
+    let value = |record: &WriteRecord| {
+        let value = record.column_value(options.column);
+        (record.counts > 0 && value.is_finite()).then_some(value)
+    };
+    let (low, high) = options.range.unwrap_or_else(|| {
+        dense_records
+            .iter()
+            .filter_map(value)
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), value| {
+                (low.min(value), high.max(value))
+            })
+    });
+    let span = if high > low { high - low } else { 1.0 };
+
+    // Each `step` cells are drawn as `scale` pixels
+    let (step, scale) = match columns > MAX_MAP_WIDTH {
+        true => (columns.div_ceil(MAX_MAP_WIDTH), 1),
+        false => (1, MIN_MAP_WIDTH.div_ceil(columns.max(1)).clamp(1, 16)),
+    };
+    let (map_width, map_height) = (
+        (columns * scale).div_ceil(step),
+        (rows * scale).div_ceil(step),
+    );
+    let width = match options.colorbar {
+        true => map_width.max(MIN_COLORBAR_WIDTH),
+        false => map_width,
+    };
+    let colorbar_height = match options.colorbar {
+        true => 3 * MARGIN + COLORBAR_HEIGHT + 7,
+        false => 0,
+    };
+    let height = map_height + colorbar_height;
+    log::info!(
+        "Drawing the {} of the {columns} by {rows} grid to a {width} by {height} image {out_file:?}...",
+        COLUMNS[options.column]
+    );
+
+    // The pixels of the graticule lines
+    let left = (width - map_width) / 2;
+    let pixel_size = grid_size * step as f64 / scale as f64;
+    let west = grid_size * (extent.west / grid_size).floor();
+    let north = grid_size * (extent.north / grid_size).ceil();
+    let (mut line_columns, mut line_rows) = (Vec::new(), Vec::new());
+    if let Some(graticule) = options.graticule {
+        let lines = |start: f64, end: f64| {
+            ((start / graticule).ceil() as i64..=(end / graticule).floor() as i64)
+                .map(move |i| i as f64 * graticule)
+        };
+        line_columns = lines(west, west + columns as f64 * grid_size)
+            .map(|lon| ((lon - west) / pixel_size).round() as usize)
+            .collect();
+        line_rows = lines(north - rows as f64 * grid_size, north)
+            .map(|lat| ((north - lat) / pixel_size).round() as usize)
+            .collect();
+    }
+
+    let mut file = OutputFile::create(out_file, clobber)?;
+    let mut encoder = png::Encoder::new(&mut file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_text_chunk("statistic".to_string(), COLUMNS[options.column].to_string())?;
+    encoder.add_text_chunk("range".to_string(), format!("{low},{high}"))?;
+    for (key, value) in metadata {
+        encoder.add_text_chunk(key.clone(), value.clone())?;
+    }
+    let mut writer = encoder.write_header()?;
+    let mut stream = writer.stream_writer()?;
+
+    let mut line = Canvas::new(width, 1, [255, 255, 255]);
+    for y in 0..map_height {
+        let cell_rows = pixel_cells(y, step, scale, rows);
+        for x in 0..map_width {
+            let cell_columns = pixel_cells(x, step, scale, columns);
+            let (sum, count) = cell_rows
+                .clone()
+                .flat_map(|row| {
+                    &dense_records[row * columns..(row + 1) * columns][cell_columns.clone()]
+                })
+                .filter_map(value)
+                .fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+            let color = match count {
+                0 => options.empty_color,
+                _ => options.colormap.color((sum / count as f64 - low) / span),
+            };
+            line.set(left + x, 0, color);
+        }
+        if line_rows.contains(&y) {
+            for x in 0..map_width {
+                line.blend(left + x, 0, LINE_COLOR);
+            }
+        } else {
+            for x in &line_columns {
+                line.blend(left + x, 0, LINE_COLOR);
+            }
+        }
+        stream.write_all(&line.pixels)?;
+    }
+
+    if options.colorbar {
+        let mut canvas = Canvas::new(width, colorbar_height, [255, 255, 255]);
+        let bar_width = width - 2 * MARGIN;
+        for x in 0..bar_width {
+            let color = options.colormap.color(x as f64 / (bar_width - 1) as f64);
+            canvas.fill(MARGIN + x, MARGIN, 1, COLORBAR_HEIGHT, color);
+        }
+        let labels = [low, (low + high) / 2.0, high].map(format_label);
+        let label_top = MARGIN + COLORBAR_HEIGHT + MARGIN;
+        let text_width = |label: &str| 6 * label.len() - 1;
+        canvas.text(MARGIN, label_top, &labels[0]);
+        canvas.text((width - text_width(&labels[1])) / 2, label_top, &labels[1]);
+        canvas.text(
+            width - MARGIN - text_width(&labels[2]),
+            label_top,
+            &labels[2],
+        );
+        stream.write_all(&canvas.pixels)?;
+    }
+    stream.finish()?;
+    writer.finish()?;
+    file.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::compute::{calc_stats, fill_grid, generate_histograms, GridOrder};
+    use crate::inputs::{sensors::GridExtent, ReadRecord};
+    use crate::outputs::files::ClobberPolicy;
+    use crate::outputs::render::{
+        format_label, parse_color, pixel_cells, write_png, Colormap, RenderOptions,
+    };
+
+    #[test]
+    /// The colormaps run between their end colors, and the labels stay short.
+    fn colormaps_and_labels() {
+        assert_eq!(Colormap::Gray.color(0.0), [0, 0, 0]);
+        assert_eq!(Colormap::Gray.color(0.5), [128, 128, 128]);
+        assert_eq!(Colormap::Viridis.color(2.0), [253, 231, 37]);
+        assert_eq!(Colormap::CoolWarm.color(0.5), [221, 221, 221]);
+
+        assert_eq!(parse_color("#d0d0d0"), Ok([208, 208, 208]));
+        assert!(parse_color("grey").is_err());
+
+        assert_eq!(format_label(1250.0), "1250");
+        assert_eq!(format_label(-0.5), "-0.5");
+        assert_eq!(format_label(123456.0), "1.23e5");
+    }
+
+    #[test]
+    /// Small grids are drawn with several pixels to a cell, and wide grids with
+    /// several cells to a pixel.
+    fn cells_of_pixels() {
+        assert_eq!(pixel_cells(0, 1, 16, 3), 0..1);
+        assert_eq!(pixel_cells(17, 1, 16, 3), 1..2);
+        assert_eq!(pixel_cells(1, 10, 1, 36000), 10..20);
+        assert_eq!(pixel_cells(3, 10, 1, 35), 30..35);
+    }
+
+    #[test]
+    /// The cells are drawn in the colormap, and empty cells in the empty color.
+    fn rendered_image() {
+        let records: Vec<ReadRecord> = [(0.5, 0.5, 1000), (1.5, 0.5, 2000)]
+            .iter()
+            .map(|(longitude, latitude, height)| ReadRecord {
+                longitude: *longitude,
+                latitude: *latitude,
+                height: *height,
+                footprint: None,
+            })
+            .collect();
+        let extent = GridExtent {
+            west: 0.0,
+            east: 3.0,
+            south: 0.0,
+            north: 1.0,
+        };
+        let grid_dict = generate_histograms(&records, &1.0).unwrap();
+        let dense = fill_grid(
+            calc_stats(&grid_dict).unwrap(),
+            1.0,
+            &extent,
+            GridOrder::RowMajor,
+        );
+        let options = RenderOptions {
+            column: 5,
+            colormap: Colormap::Gray,
+            range: None,
+            empty_color: [255, 0, 0],
+            graticule: None,
+            colorbar: false,
+        };
+
+        let path =
+            std::env::temp_dir().join(format!("grid_test_render_{}.png", std::process::id()));
+        write_png(
+            &dense,
+            &path,
+            ClobberPolicy::Overwrite,
+            &[],
+            1.0,
+            &extent,
+            &options,
+        )
+        .unwrap();
+
+        let decoder = png::Decoder::new(fs::File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        fs::remove_file(path).unwrap();
+
+        // The cells of small grids are drawn at most 16 pixels wide
+        assert_eq!((info.width, info.height), (48, 16));
+        let pixel = |x: usize| &pixels[3 * x..3 * x + 3];
+        assert_eq!(pixel(0), [0, 0, 0]);
+        assert_eq!(pixel(20), [255, 255, 255]);
+        assert_eq!(pixel(40), [255, 0, 0]);
+    }
+}
@@ -0,0 +1,254 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use clap::ArgMatches;
+
+use crate::{
+    inputs::compression::is_stdio,
+    outputs::files::{ClobberPolicy, OutputFile},
+};
+
+/// The version of grid_test, as recorded in the run report and output metadata.
+pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The InputFileReport struct describes a single input file of the run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InputFileReport {
+    pub path: PathBuf,
+    pub size_bytes: u64,
+    /// Only computed when a run report is asked for, as it means reading the file again.
+    pub sha256: Option<String>,
+    pub records_read: usize,
+    pub records_rejected: usize,
+    pub truncated: bool,
+}
+
+impl InputFileReport {
+    /// Describes the input file, without computing its checksum. The size of
+    /// stdin is unknown, and given as zero.
+    pub fn new(path: &Path) -> Result<InputFileReport, Box<dyn Error>> {
+        let size_bytes = match is_stdio(path) {
+            true => 0,
+            false => path.metadata()?.len(),
+        };
+        Ok(InputFileReport {
+            path: path.to_path_buf(),
+            size_bytes,
+            sha256: None,
+            records_read: 0,
+            records_rejected: 0,
+            truncated: false,
+        })
+    }
+
+    /// Computes the SHA-256 checksum of the file. This isn't possible for stdin.
+    pub fn compute_checksum(&mut self) -> Result<(), Box<dyn Error>> {
+        if is_stdio(&self.path) {
+            return Ok(());
+        }
+        let mut file = File::open(&self.path)?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut file, &mut hasher)?;
+        let sha256: String = hasher
+            .finalize()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        self.sha256 = Some(sha256);
+        Ok(())
+    }
+}
+
+/// The GridSpec struct describes the grid the records were binned into.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GridSpec {
+    /// Description of the grid, such as the projection.
+    pub grid: String,
+    pub grid_size: f64,
+    pub sensor: Option<String>,
+    pub native_resolution_km: Option<f64>,
+    pub grid_extent: Option<String>,
+}
+
+/// The OutputReport struct describes one of the outputs written.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OutputReport {
+    pub path: PathBuf,
+    pub grid_size: f64,
+    pub populated_cells: usize,
+}
+
+/// The StageTiming struct holds the wall-clock time spent in one stage of the run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageTiming {
+    pub stage: String,
+    pub seconds: f64,
+}
+
+/// The RunReport struct holds the provenance of a run of grid_test.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunReport {
+    pub tool: String,
+    pub tool_version: String,
+    pub created: String,
+    pub arguments: BTreeMap<String, Vec<String>>,
+    pub inputs: Vec<InputFileReport>,
+    pub records_read: usize,
+    pub records_rejected: usize,
+    pub grid: GridSpec,
+    /// The regions the observations were selected from, if any.
+    pub regions: Vec<String>,
+    pub statistics: Vec<String>,
+    pub populated_cells: usize,
+    pub outputs: Vec<OutputReport>,
+    pub timings: Vec<StageTiming>,
+}
+
+impl RunReport {
+    pub fn new(grid: GridSpec) -> Self {
+        RunReport {
+            tool: "grid_test".to_string(),
+            tool_version: TOOL_VERSION.to_string(),
+            created: chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            arguments: BTreeMap::new(),
+            inputs: Vec::new(),
+            records_read: 0,
+            records_rejected: 0,
+            grid,
+            regions: Vec::new(),
+            statistics: Vec::new(),
+            populated_cells: 0,
+            outputs: Vec::new(),
+            timings: Vec::new(),
+        }
+    }
+
+    /// Records the resolved value of every argument, including the defaults.
+    pub fn set_arguments(&mut self, args: &ArgMatches) {
+        self.arguments = args
+            .ids()
+            .filter_map(|id| {
+                let values: Vec<String> = args
+                    .try_get_raw(id.as_str())
+                    .ok()??
+                    .map(|v| v.to_string_lossy().to_string())
+                    .collect();
+                Some((id.to_string(), values))
+            })
+            .collect();
+    }
+
+    /// Records the time since `start` against the stage.
+    pub fn add_timing(&mut self, stage: &str, start: Instant) {
+        self.timings.push(StageTiming {
+            stage: stage.to_string(),
+            seconds: start.elapsed().as_secs_f64(),
+        });
+    }
+
+    /// The key parts of the report, for embedding as metadata in the outputs.
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let mut metadata: Vec<(String, String)> = vec![
+            (
+                "history".to_string(),
+                format!("{} {}", self.tool, self.tool_version),
+            ),
+            ("created".to_string(), self.created.clone()),
+            (
+                "sensor".to_string(),
+                self.grid.sensor.clone().unwrap_or("unknown".to_string()),
+            ),
+        ];
+        if let Some(native_resolution) = self.grid.native_resolution_km {
+            metadata.push((
+                "native_resolution_km".to_string(),
+                native_resolution.to_string(),
+            ));
+        }
+        if let Some(grid_extent) = &self.grid.grid_extent {
+            metadata.push(("grid_extent".to_string(), grid_extent.clone()));
+        }
+        metadata.push(("grid".to_string(), self.grid.grid.clone()));
+        metadata.push(("grid_size".to_string(), self.grid.grid_size.to_string()));
+        if !self.regions.is_empty() {
+            metadata.push(("regions".to_string(), self.regions.join(",")));
+        }
+        metadata.push(("input_files".to_string(), self.inputs.len().to_string()));
+        metadata.push(("records_read".to_string(), self.records_read.to_string()));
+        metadata.push((
+            "records_rejected".to_string(),
+            self.records_rejected.to_string(),
+        ));
+        metadata.push((
+            "populated_cells".to_string(),
+            self.populated_cells.to_string(),
+        ));
+        metadata
+    }
+
+    /// Writes the report as JSON.
+    pub fn write(&self, report_file: &Path, clobber: ClobberPolicy) -> Result<(), Box<dyn Error>> {
+        log::info!("Writing the run report to {report_file:?}...");
+
+        let mut writer = OutputFile::create(report_file, clobber)?;
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.commit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::outputs::report::{GridSpec, InputFileReport, RunReport};
+
+    #[test]
+    /// The checksum and size are those of the file contents.
+    fn input_file_checksum() {
+        let path = std::env::temp_dir().join(format!("grid_test_sha_{}.csv", std::process::id()));
+        fs::write(&path, "abc").unwrap();
+
+        let mut input = InputFileReport::new(&path).unwrap();
+        assert_eq!(input.size_bytes, 3);
+        assert_eq!(input.sha256, None);
+        input.compute_checksum().unwrap();
+        assert_eq!(
+            input.sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    /// The report serializes to JSON with the expected fields.
+    fn report_to_json() {
+        let mut report = RunReport::new(GridSpec {
+            grid: "lon/lat 0.5 degree".to_string(),
+            grid_size: 0.5,
+            sensor: Some("VIIRS".to_string()),
+            native_resolution_km: None,
+            grid_extent: None,
+        });
+        report.populated_cells = 12;
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["tool"], "grid_test");
+        assert_eq!(json["grid"]["grid_size"], 0.5);
+        assert_eq!(json["populated_cells"], 12);
+
+        let metadata = report.metadata();
+        assert!(metadata.contains(&("sensor".to_string(), "VIIRS".to_string())));
+        assert!(metadata.contains(&("populated_cells".to_string(), "12".to_string())));
+    }
+}
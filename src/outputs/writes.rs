@@ -1,7 +1,36 @@
-use serde::Serialize;
-use std::{error::Error, path::PathBuf};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
 
-#[derive(Debug, Serialize, Clone, PartialEq)]
+use crate::compute::compare::{CellComparison, ComparisonSummary};
+use crate::compute::means::{AreaMean, MeanLabel, MeanTable};
+use crate::compute::summaries::RegionSummary;
+use crate::outputs::files::{ClobberPolicy, OutputFile};
+use crate::progress::Progress;
+use std::{
+    error::Error,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The statistics columns of the WriteRecord struct.
+pub const STATISTICS: [&str; 5] = [
+    "Counts",
+    "SumHeights",
+    "SumSquaredHeights",
+    "MeanHeight",
+    "StdevHeight",
+];
+
+/// Reads a missing value, written as an empty field or null, as NaN.
+fn nan_if_missing<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN))
+}
+
+fn missing() -> f64 {
+    f64::NAN
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "PascalCase")]
 pub struct WriteRecord {
     pub longitude: f64,
@@ -9,29 +38,248 @@ pub struct WriteRecord {
     pub counts: i64,
     pub sum_heights: i64,
     pub sum_squared_heights: i64,
+    #[serde(deserialize_with = "nan_if_missing")]
     pub mean_height: f64,
+    #[serde(deserialize_with = "nan_if_missing")]
     pub stdev_height: f64,
+    /// Area of the grid cell, in km².
+    #[serde(default = "missing", deserialize_with = "nan_if_missing")]
+    pub cell_area: f64,
+}
+
+/// All of the columns of the WriteRecord struct, in order. The cell area is
+/// only written by default for the projected grids.
+pub const COLUMNS: [&str; 8] = [
+    "Longitude",
+    "Latitude",
+    STATISTICS[0],
+    STATISTICS[1],
+    STATISTICS[2],
+    STATISTICS[3],
+    STATISTICS[4],
+    "CellArea",
+];
+
+/// A value of a WriteRecord, which is formatted according to its column group.
+enum Value {
+    Coordinate(f64),
+    Count(i64),
+    Statistic(f64),
+}
+
+impl WriteRecord {
+    /// The values of the record, in the order of COLUMNS.
+    fn values(&self) -> [Value; 8] {
+        [
+            Value::Coordinate(self.longitude),
+            Value::Coordinate(self.latitude),
+            Value::Count(self.counts),
+            Value::Count(self.sum_heights),
+            Value::Count(self.sum_squared_heights),
+            Value::Statistic(self.mean_height),
+            Value::Statistic(self.stdev_height),
+            Value::Statistic(self.cell_area),
+        ]
+    }
+
+    /// The value of the column of COLUMNS as a float, for the outputs holding numbers.
+    pub fn column_value(&self, index: usize) -> f64 {
+        match self.values()[index] {
+            Value::Coordinate(value) | Value::Statistic(value) => value,
+            Value::Count(value) => value as f64,
+        }
+    }
+}
+
+/// The CsvFormat struct holds how the values and columns of the csv output are written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFormat {
+    /// Decimal places of the longitude and latitude, or the shortest exact value.
+    pub coordinate_precision: Option<usize>,
+    /// Decimal places of the mean and standard deviation, or the shortest exact value.
+    pub statistic_precision: Option<usize>,
+    /// Write the floating point values in scientific notation.
+    pub scientific: bool,
+    /// Written in place of NaN, such as the mean and standard deviation of empty cells.
+    pub missing_value: String,
+    /// The (index into COLUMNS, header name) of the columns to write, in order.
+    pub columns: Vec<(usize, String)>,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        CsvFormat {
+            coordinate_precision: None,
+            statistic_precision: None,
+            scientific: false,
+            missing_value: String::new(),
+            columns: COLUMNS[..7]
+                .iter()
+                .enumerate()
+                .map(|(i, name)| (i, name.to_string()))
+                .collect(),
+        }
+    }
+}
+
+/// Finds the index into COLUMNS of the named column, ignoring case.
+fn column_index(name: &str) -> Result<usize, Box<dyn Error>> {
+    COLUMNS
+        .iter()
+        .position(|c| c.eq_ignore_ascii_case(name.trim()))
+        .ok_or_else(|| {
+            format!(
+                "Unknown column {name:?}, expected one of {}",
+                COLUMNS.join(", ")
+            )
+            .into()
+        })
+}
+
+impl CsvFormat {
+    /// Selects (and optionally renames) the columns, given as "NAME" or "NAME=NEW_NAME".
+    pub fn select_columns(&mut self, columns: &[String]) -> Result<(), Box<dyn Error>> {
+        self.columns = columns
+            .iter()
+            .map(|column| {
+                let (name, rename) = column.split_once('=').unwrap_or((column, column));
+                Ok((column_index(name)?, rename.trim().to_string()))
+            })
+            .collect::<Result<_, Box<dyn Error>>>()?;
+        Ok(())
+    }
+
+    /// Appends the named column, under its own name, to the written columns.
+    pub fn add_column(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let index = column_index(name)?;
+        self.columns.push((index, COLUMNS[index].to_string()));
+        Ok(())
+    }
+
+    fn format_float(&self, value: f64, precision: Option<usize>) -> String {
+        if value.is_nan() {
+            return self.missing_value.clone();
+        }
+        match (self.scientific, precision) {
+            (false, None) => format!("{value:?}"),
+            (false, Some(precision)) => format!("{value:.precision$}"),
+            (true, None) => format!("{value:e}"),
+            (true, Some(precision)) => format!("{value:.precision$e}"),
+        }
+    }
+
+    fn format(&self, value: &Value) -> String {
+        match value {
+            Value::Coordinate(value) => self.format_float(*value, self.coordinate_precision),
+            Value::Count(value) => value.to_string(),
+            Value::Statistic(value) => self.format_float(*value, self.statistic_precision),
+        }
+    }
+
+    /// A coordinate rounded to the decimal places of the format, for the outputs
+    /// which hold numbers rather than text.
+    pub fn round_coordinate(&self, value: f64) -> f64 {
+        round(value, self.coordinate_precision)
+    }
+
+    /// The selected columns of the record as JSON values, with NaN as null.
+    pub fn json_fields(&self, record: &WriteRecord) -> serde_json::Map<String, serde_json::Value> {
+        let values = record.values();
+        self.columns
+            .iter()
+            .map(|(index, name)| {
+                let value = match &values[*index] {
+                    Value::Coordinate(value) => json!(round(*value, self.coordinate_precision)),
+                    Value::Count(value) => json!(value),
+                    Value::Statistic(value) => json!(round(*value, self.statistic_precision)),
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    }
+
+    /// The formatted fields of the selected columns of the record.
+    fn fields(&self, record: &WriteRecord) -> Vec<String> {
+        let values = record.values();
+        self.columns
+            .iter()
+            .map(|(index, _)| self.format(&values[*index]))
+            .collect()
+    }
+}
+
+/// The value rounded to the decimal places, if given. NaN is left alone, and
+/// becomes null in JSON.
+fn round(value: f64, precision: Option<usize>) -> f64 {
+    match precision {
+        Some(precision) => {
+            let scale = 10.0_f64.powi(precision as i32);
+            (value * scale).round() / scale
+        }
+        None => value,
+    }
+}
+
+/// Creates a csv output, writing the metadata key/value pairs as "# key: value" comment
+/// lines and then the header. The rows are written to the returned writer, and the
+/// output is committed once they're all written.
+fn create_csv_writer<I, T>(
+    out_file: &Path,
+    clobber: ClobberPolicy,
+    metadata: &[(String, String)],
+    header: I,
+) -> Result<csv::Writer<OutputFile>, Box<dyn Error>>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut file = OutputFile::create(out_file, clobber)?;
+    for (key, value) in metadata {
+        writeln!(file, "# {key}: {value}")?;
+    }
+
+    let mut wtr = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(file);
+    wtr.write_record(header)?;
+    Ok(wtr)
 }
 
 /// This function accepts as input a vector of WriteRecord structs, and an output filename,
-/// and serializes the vector to the output file.
+/// and serializes the vector to the output file. The metadata key/value pairs are written
+/// as "# key: value" comment lines ahead of the csv header. The output is compressed
+/// according to its extension, and only appears once completely written. The values and
+/// columns are written according to the format.
 pub fn write_csv_using_serde(
     csv_records: &Vec<WriteRecord>,
     out_file: &PathBuf,
+    clobber: ClobberPolicy,
+    format: &CsvFormat,
+    metadata: &[(String, String)],
+    progress: &Progress,
     // out_file: &String,
 ) -> Result<(), Box<dyn Error>> {
     log::info!("Serializing the histogram data to file {out_file:?}...");
 
-    let mut wtr = csv::Writer::from_path(out_file)?;
+    let mut wtr = create_csv_writer(
+        out_file,
+        clobber,
+        metadata,
+        format.columns.iter().map(|(_, name)| name),
+    )?;
 
     let mut num_grids_cells: i64 = 0;
 
     for record in csv_records {
-        wtr.serialize(record)?;
+        wtr.write_record(format.fields(record))?;
         num_grids_cells += 1;
+        if num_grids_cells % 1000 == 0 {
+            progress.update(num_grids_cells as u64, 0);
+        }
     }
+    progress.finish();
 
-    wtr.flush()?;
+    wtr.into_inner().map_err(|e| e.to_string())?.commit()?;
 
     log::info!("Finished serializing the histogram data to a csv file...");
     log::info!("There are {:?} entries in the csv file.\n", num_grids_cells);
@@ -40,8 +288,174 @@ pub fn write_csv_using_serde(
     Ok(())
 }
 
+/// The columns of the tables of means, after the column labelling the rows.
+pub const MEAN_COLUMNS: [&str; 4] = ["Cells", "Counts", "Weight", "MeanHeight"];
+
+/// This function writes a table of means to a csv file, after the metadata
+/// comment lines. The coordinates and means are formatted as in the cell grid.
+pub fn write_means(
+    means: &[AreaMean],
+    table: MeanTable,
+    out_file: &PathBuf,
+    clobber: ClobberPolicy,
+    format: &CsvFormat,
+    metadata: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    log::info!("Writing the {} means to file {out_file:?}...", table.name());
+
+    let mut wtr = create_csv_writer(
+        out_file,
+        clobber,
+        metadata,
+        [table.label_column()].iter().chain(&MEAN_COLUMNS),
+    )?;
+    for mean in means {
+        let label = match &mean.label {
+            MeanLabel::Coordinate(value) => format.format(&Value::Coordinate(*value)),
+            MeanLabel::Region(name) => name.to_string(),
+        };
+        wtr.write_record([
+            label,
+            mean.cells.to_string(),
+            mean.counts.to_string(),
+            format.format(&Value::Statistic(mean.weight)),
+            format.format(&Value::Statistic(mean.mean_height)),
+        ])?;
+    }
+    wtr.into_inner().map_err(|e| e.to_string())?.commit()?;
+
+    Ok(())
+}
+
+/// The columns of the region summaries.
+pub const REGION_COLUMNS: [&str; 8] = [
+    "Region",
+    "Cells",
+    COLUMNS[7],
+    STATISTICS[0],
+    STATISTICS[1],
+    STATISTICS[2],
+    STATISTICS[3],
+    STATISTICS[4],
+];
+
+/// This function writes the summaries of the regions to a csv file, after the
+/// metadata comment lines. The statistics are formatted as in the cell grid.
+pub fn write_region_summaries(
+    summaries: &[RegionSummary],
+    out_file: &PathBuf,
+    clobber: ClobberPolicy,
+    format: &CsvFormat,
+    metadata: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    log::info!("Writing the region summaries to file {out_file:?}...");
+
+    let mut wtr = create_csv_writer(out_file, clobber, metadata, REGION_COLUMNS)?;
+    for summary in summaries {
+        wtr.write_record([
+            summary.region.clone(),
+            summary.cells.to_string(),
+            format.format(&Value::Statistic(summary.cell_area)),
+            format.format(&Value::Count(summary.counts)),
+            format.format(&Value::Count(summary.sum_heights)),
+            format.format(&Value::Count(summary.sum_squared_heights)),
+            format.format(&Value::Statistic(summary.mean_height)),
+            format.format(&Value::Statistic(summary.stdev_height)),
+        ])?;
+    }
+    wtr.into_inner().map_err(|e| e.to_string())?.commit()?;
+
+    Ok(())
+}
+
+/// The columns of the cell by cell comparison of two grids.
+pub const COMPARISON_COLUMNS: [&str; 9] = [
+    COLUMNS[0],
+    COLUMNS[1],
+    "BaseCounts",
+    "NewCounts",
+    "CountChange",
+    "BaseMeanHeight",
+    "NewMeanHeight",
+    "Difference",
+    "Ratio",
+];
+
+/// The columns of the summary of the comparison of two grids.
+pub const COMPARISON_SUMMARY_COLUMNS: [&str; 9] = [
+    "ComparedCells",
+    "BaseOnlyCells",
+    "NewOnlyCells",
+    "CountChange",
+    "Bias",
+    "RMSE",
+    "Correlation",
+    "Tolerance",
+    "ChangedFraction",
+];
+
+/// This function writes the comparison of the cells of two grids to a csv file,
+/// after the metadata comment lines. The coordinates and heights are formatted
+/// as in the cell grid.
+pub fn write_comparison(
+    cells: &[CellComparison],
+    out_file: &PathBuf,
+    clobber: ClobberPolicy,
+    format: &CsvFormat,
+    metadata: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    log::info!("Writing the comparison of the grids to file {out_file:?}...");
+
+    let mut wtr = create_csv_writer(out_file, clobber, metadata, COMPARISON_COLUMNS)?;
+    for cell in cells {
+        wtr.write_record([
+            format.format(&Value::Coordinate(cell.longitude)),
+            format.format(&Value::Coordinate(cell.latitude)),
+            format.format(&Value::Count(cell.base_counts)),
+            format.format(&Value::Count(cell.new_counts)),
+            format.format(&Value::Count(cell.count_change())),
+            format.format(&Value::Statistic(cell.base_mean)),
+            format.format(&Value::Statistic(cell.new_mean)),
+            format.format(&Value::Statistic(cell.difference())),
+            format.format(&Value::Statistic(cell.ratio())),
+        ])?;
+    }
+    wtr.into_inner().map_err(|e| e.to_string())?.commit()?;
+
+    Ok(())
+}
+
+/// This function writes the summary of the comparison of two grids to a csv
+/// file, after the metadata comment lines.
+pub fn write_comparison_summary(
+    summary: &ComparisonSummary,
+    out_file: &PathBuf,
+    clobber: ClobberPolicy,
+    format: &CsvFormat,
+    metadata: &[(String, String)],
+) -> Result<(), Box<dyn Error>> {
+    log::info!("Writing the summary of the comparison to file {out_file:?}...");
+
+    let mut wtr = create_csv_writer(out_file, clobber, metadata, COMPARISON_SUMMARY_COLUMNS)?;
+    wtr.write_record([
+        summary.compared_cells.to_string(),
+        summary.base_only_cells.to_string(),
+        summary.new_only_cells.to_string(),
+        format.format(&Value::Count(summary.count_change)),
+        format.format(&Value::Statistic(summary.bias)),
+        format.format(&Value::Statistic(summary.rmse)),
+        format.format(&Value::Statistic(summary.correlation)),
+        format.format(&Value::Statistic(summary.tolerance)),
+        format.format(&Value::Statistic(summary.changed_fraction)),
+    ])?;
+    wtr.into_inner().map_err(|e| e.to_string())?.commit()?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::outputs::writes::CsvFormat;
     use crate::WriteRecord;
     #[test]
     /// This test checks that the struct attributes are the values
@@ -55,6 +469,7 @@ mod tests {
             sum_squared_heights: 23456789123,
             mean_height: 23456.0,
             stdev_height: 123.5,
+            cell_area: 12345.6,
         };
         assert_eq!(record.longitude, 100.0);
         assert_eq!(record.latitude, 35.2);
@@ -77,8 +492,56 @@ mod tests {
             sum_squared_heights: 23456789123,
             mean_height: 23456.0,
             stdev_height: 123.5,
+            cell_area: 12345.6,
         };
         let record_2 = record_1.clone();
         assert_eq!(record_1, record_2);
     }
+
+    #[test]
+    /// The values are written to the precision asked for, with the columns selected.
+    fn csv_format() {
+        let record = WriteRecord {
+            longitude: 100.25,
+            latitude: -35.75,
+            counts: 3,
+            sum_heights: 3700,
+            sum_squared_heights: 4690000,
+            mean_height: 1233.3333333333333,
+            stdev_height: f64::NAN,
+            cell_area: 702.4,
+        };
+        let mut format = CsvFormat::default();
+        assert_eq!(
+            format.fields(&record),
+            [
+                "100.25",
+                "-35.75",
+                "3",
+                "3700",
+                "4690000",
+                "1233.3333333333333",
+                ""
+            ]
+        );
+
+        format.coordinate_precision = Some(1);
+        format.statistic_precision = Some(2);
+        format.missing_value = "NaN".to_string();
+        assert_eq!(
+            format.fields(&record),
+            ["100.2", "-35.8", "3", "3700", "4690000", "1233.33", "NaN"]
+        );
+
+        format.scientific = true;
+        format
+            .select_columns(&["latitude".to_string(), "MeanHeight=cth".to_string()])
+            .unwrap();
+        assert_eq!(
+            format.columns,
+            [(1, "latitude".to_string()), (5, "cth".to_string())]
+        );
+        assert_eq!(format.fields(&record), ["-3.6e1", "1.23e3"]);
+        assert!(format.select_columns(&["Height".to_string()]).is_err());
+    }
 }
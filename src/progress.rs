@@ -0,0 +1,220 @@
+use std::{
+    cell::Cell,
+    io::{self, IsTerminal},
+    time::{Duration, Instant},
+};
+
+use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressStyle};
+
+/// How often a progress line is written when stderr isn't a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How the progress of the run is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A progress bar on stderr.
+    Bar,
+    /// Periodic lines on stderr.
+    Log,
+    /// Nothing.
+    Hidden,
+}
+
+impl ProgressMode {
+    /// A progress bar if stderr is a terminal, otherwise progress lines. Nothing is
+    /// shown with --quiet.
+    pub fn detect(quiet: bool) -> Self {
+        if quiet {
+            ProgressMode::Hidden
+        } else if io::stderr().is_terminal() {
+            ProgressMode::Bar
+        } else {
+            ProgressMode::Log
+        }
+    }
+}
+
+/// The Progress struct tracks the progress of one stage of the run, in bytes
+/// (when reading) or items, along with the files and records done.
+pub struct Progress {
+    mode: ProgressMode,
+    stage: String,
+    bar: ProgressBar,
+    total_files: u64,
+    files_done: Cell<u64>,
+    records: Cell<u64>,
+    start: Instant,
+    last_log: Cell<Instant>,
+}
+
+impl Progress {
+    /// Progress through `total_bytes` of input spread over `total_files` files.
+    pub fn bytes(mode: ProgressMode, stage: &str, total_bytes: u64, total_files: u64) -> Self {
+        let style = ProgressStyle::with_template(
+            "{prefix:>10} [{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} ({eta}) {msg}",
+        )
+        .unwrap();
+        Progress::new(mode, stage, total_bytes, total_files, style)
+    }
+
+    /// Progress through `total_items`, such as grid cells. The total may grow
+    /// as the stage goes on, see `add_items`.
+    pub fn items(mode: ProgressMode, stage: &str, total_items: u64) -> Self {
+        let style = ProgressStyle::with_template(
+            "{prefix:>10} [{elapsed_precise}] {wide_bar} {human_pos}/{human_len} ({eta})",
+        )
+        .unwrap();
+        Progress::new(mode, stage, total_items, 0, style)
+    }
+
+    /// No progress is shown.
+    pub fn hidden() -> Self {
+        Progress::items(ProgressMode::Hidden, "", 0)
+    }
+
+    fn new(
+        mode: ProgressMode,
+        stage: &str,
+        total: u64,
+        total_files: u64,
+        style: ProgressStyle,
+    ) -> Self {
+        // The bars are drawn together, and hidden while messages are logged
+        let bar = match mode {
+            ProgressMode::Bar => data::progress_bars().add(ProgressBar::new(total)),
+            ProgressMode::Log | ProgressMode::Hidden => ProgressBar::hidden(),
+        };
+        bar.set_length(total);
+        bar.set_style(style.progress_chars("=> "));
+        bar.set_prefix(stage.to_string());
+
+        let now = Instant::now();
+        Progress {
+            mode,
+            stage: stage.to_string(),
+            bar,
+            total_files,
+            files_done: Cell::new(0),
+            records: Cell::new(0),
+            start: now,
+            last_log: Cell::new(now),
+        }
+    }
+
+    /// Moves the progress on to `position` bytes or items, with `records` more records done.
+    pub fn update(&self, position: u64, records: u64) {
+        self.records.set(self.records.get() + records);
+        self.bar.set_position(position);
+        self.refresh();
+    }
+
+    /// Adds `items` to the total, for stages whose total isn't known up front.
+    pub fn add_items(&self, items: u64) {
+        self.bar.inc_length(items);
+    }
+
+    /// Marks another file as done.
+    pub fn file_done(&self) {
+        self.files_done.set(self.files_done.get() + 1);
+        self.refresh();
+    }
+
+    fn records_per_sec(&self) -> f64 {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        if elapsed > 0.0 {
+            self.records.get() as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+
+    fn message(&self) -> String {
+        if self.total_files == 0 {
+            return String::new();
+        }
+        format!(
+            "files {}/{}, {} records ({}/s)",
+            self.files_done.get(),
+            self.total_files,
+            HumanCount(self.records.get()),
+            HumanCount(self.records_per_sec() as u64),
+        )
+    }
+
+    fn refresh(&self) {
+        match self.mode {
+            ProgressMode::Bar => self.bar.set_message(self.message()),
+            ProgressMode::Log => {
+                if self.last_log.get().elapsed() >= LOG_INTERVAL {
+                    self.last_log.set(Instant::now());
+                    self.log();
+                }
+            }
+            ProgressMode::Hidden => {}
+        }
+    }
+
+    /// Writes a progress line to stderr. It's written outside of the log, so it's
+    /// shown at any log level but kept out of the log file and JSON logs.
+    fn log(&self) {
+        let position = self.bar.position();
+        let length = self.bar.length().unwrap_or(0);
+        let percent = if length > 0 {
+            100.0 * position as f64 / length as f64
+        } else {
+            100.0
+        };
+        let done = if self.total_files > 0 {
+            format!("{}/{}", HumanBytes(position), HumanBytes(length))
+        } else {
+            format!("{position}/{length}")
+        };
+        let line = format!(
+            "{}: {percent:.0}% ({done}, eta {:.0?}) {}",
+            self.stage,
+            self.bar.eta(),
+            self.message()
+        );
+        eprintln!("{}", line.trim_end());
+    }
+
+    /// Finishes the stage, clearing the progress bar.
+    pub fn finish(&self) {
+        match self.mode {
+            ProgressMode::Bar => {
+                self.bar.finish_and_clear();
+                data::progress_bars().remove(&self.bar);
+            }
+            ProgressMode::Log => {
+                self.bar.set_position(self.bar.length().unwrap_or(0));
+                self.log();
+            }
+            ProgressMode::Hidden => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{Progress, ProgressMode};
+
+    #[test]
+    /// Nothing is shown with --quiet.
+    fn quiet_hides_progress() {
+        assert_eq!(ProgressMode::detect(true), ProgressMode::Hidden);
+    }
+
+    #[test]
+    /// The files and records done are counted.
+    fn progress_counts() {
+        let progress = Progress::bytes(ProgressMode::Hidden, "reading", 1000, 2);
+        progress.update(400, 10);
+        progress.file_done();
+        progress.update(1000, 15);
+        progress.file_done();
+        progress.finish();
+        assert_eq!(progress.files_done.get(), 2);
+        assert_eq!(progress.records.get(), 25);
+        assert!(progress.message().starts_with("files 2/2, 25 records"));
+    }
+}